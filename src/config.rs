@@ -0,0 +1,621 @@
+mod yaml_edit;
+
+use crate::feishu::{self, BotConfig, EventConfig, LiveConfig, LiveMode, StatusConfig};
+use crate::services::export_handler::AssConfig;
+use crate::services::library_handler::LibraryConfig;
+use crate::services::secret_handler::SecretKey;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use yaml_edit::{Segment, YamlEdit};
+
+/// Environment variables that override values from the config file
+const ENV_USER_TOKEN: &str = "SIREN_USER_TOKEN";
+const ENV_USER_TOKEN_FILE: &str = "SIREN_USER_TOKEN_FILE";
+const ENV_APP_ID: &str = "SIREN_APP_ID";
+const ENV_APP_SECRET: &str = "SIREN_APP_SECRET";
+const ENV_USER_LIST: &str = "SIREN_USER_LIST";
+
+/// Fields that may be stored as `enc:` values
+const SECRET_FIELDS: [&str; 2] = ["user_token", "app_secret"];
+const EVENT_SECRET_FIELDS: [&str; 2] = ["encrypt_key", "verification_token"];
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub user_token: Option<String>,
+    pub user_token_file: Option<PathBuf>,
+    /// `feishu`, `lark` or the address of a private deployment
+    pub domain: String,
+    pub app_id: String,
+    pub app_secret: String,
+    pub user_list: Vec<String>,
+    /// Additional feishu apps, each with its own users
+    pub tenants: Vec<TenantConfig>,
+    pub status: StatusConfig,
+    /// Send a message card to a group chat when a new track starts
+    pub bot: Option<BotConfig>,
+    /// Receive `/siren` chat commands through the feishu event subscription
+    pub events: Option<EventConfig>,
+    pub lyrics: LyricsConfig,
+}
+
+/// Options of the lyrics exports
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LyricsConfig {
+    pub ass: AssConfig,
+    /// Local lyrics files, used before Apple Music
+    pub library: Option<LibraryConfig>,
+}
+
+#[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TenantConfig {
+    pub name: Option<String>,
+    pub domain: String,
+    pub app_id: String,
+    pub app_secret: String,
+    pub user_list: Vec<String>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("user_token", &self.user_token.as_ref().map(|_| "***"))
+            .field("user_token_file", &self.user_token_file)
+            .field("domain", &self.domain)
+            .field("app_id", &self.app_id)
+            .field("app_secret", &"***")
+            .field("user_list", &self.user_list)
+            .field("tenants", &self.tenants)
+            .field("status", &self.status)
+            .field("bot", &self.bot)
+            .field("events", &self.events)
+            .field("lyrics", &self.lyrics)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TenantConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantConfig")
+            .field("name", &self.name)
+            .field("domain", &self.domain)
+            .field("app_id", &self.app_id)
+            .field("app_secret", &"***")
+            .field("user_list", &self.user_list)
+            .finish()
+    }
+}
+
+impl TenantConfig {
+    /// Name used in logs, defaults to the app id
+    pub(crate) fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.app_id)
+    }
+}
+
+impl Config {
+    /// Load config from file, apply `SIREN_*` overrides, decrypt secrets and validate it
+    pub(crate) fn load(path: &Path, key: Option<&SecretKey>) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read config file {}: {}", path.display(), error))?;
+        let mut config = Self::parse(&contents).map_err(|error| {
+            format!("Unable to parse config file {}: {}", path.display(), error)
+        })?;
+        config.apply_env_overrides(|key| std::env::var(key).ok());
+        config.read_user_token_file()?;
+        config
+            .decrypt_secrets(key)
+            .map_err(|error| format!("Invalid config file {}:\n{}", path.display(), error))?;
+        config
+            .validate()
+            .map_err(|error| format!("Invalid config file {}:\n{}", path.display(), error))?;
+        Ok(config)
+    }
+
+    /// Every feishu app to update, the top level app first
+    pub(crate) fn tenants(&self) -> Vec<TenantConfig> {
+        let mut tenants: Vec<TenantConfig> = Vec::new();
+        if self.has_default_tenant() {
+            tenants.push(self.default_tenant());
+        }
+        tenants.extend(self.tenants.iter().cloned());
+        tenants
+    }
+
+    fn default_tenant(&self) -> TenantConfig {
+        TenantConfig {
+            name: None,
+            domain: self.domain.clone(),
+            app_id: self.app_id.clone(),
+            app_secret: self.app_secret.clone(),
+            user_list: self.user_list.clone(),
+        }
+    }
+
+    fn has_default_tenant(&self) -> bool {
+        !self.app_id.is_empty() || !self.app_secret.is_empty() || !self.user_list.is_empty()
+    }
+
+    /// Describe what changed between two configs, without printing secrets
+    pub(crate) fn diff(&self, new: &Config) -> Vec<String> {
+        let mut changes: Vec<String> = Vec::new();
+
+        if new.user_token.is_some() && self.user_token != new.user_token {
+            changes.push("user_token changed".to_string());
+        }
+        Self::diff_tenant(
+            "",
+            &self.default_tenant(),
+            &new.default_tenant(),
+            &mut changes,
+        );
+        for tenant in &new.tenants {
+            let prefix = format!("tenants[{}].", tenant.label());
+            match self
+                .tenants
+                .iter()
+                .find(|old| old.label() == tenant.label())
+            {
+                Some(old) => Self::diff_tenant(&prefix, old, tenant, &mut changes),
+                None => changes.push(format!("tenants: + {}", tenant.label())),
+            }
+        }
+        for tenant in &self.tenants {
+            if !new.tenants.iter().any(|new| new.label() == tenant.label()) {
+                changes.push(format!("tenants: - {}", tenant.label()));
+            }
+        }
+        if self.status != new.status {
+            changes.push("status changed".to_string());
+        }
+        if self.bot != new.bot {
+            changes.push("bot changed".to_string());
+        }
+        if self.events != new.events {
+            changes.push("events changed".to_string());
+        }
+        if self.lyrics != new.lyrics {
+            changes.push("lyrics changed".to_string());
+        }
+        changes
+    }
+
+    fn diff_tenant(
+        prefix: &str,
+        old: &TenantConfig,
+        new: &TenantConfig,
+        changes: &mut Vec<String>,
+    ) {
+        if old.domain != new.domain {
+            changes.push(format!(
+                "{}domain: {} -> {}",
+                prefix, old.domain, new.domain
+            ));
+        }
+        if old.app_id != new.app_id {
+            changes.push(format!(
+                "{}app_id: {} -> {}",
+                prefix, old.app_id, new.app_id
+            ));
+        }
+        if old.app_secret != new.app_secret {
+            changes.push(format!("{}app_secret changed", prefix));
+        }
+        for user_id in &new.user_list {
+            if !old.user_list.contains(user_id) {
+                changes.push(format!("{}user_list: + {}", prefix, user_id));
+            }
+        }
+        for user_id in &old.user_list {
+            if !new.user_list.contains(user_id) {
+                changes.push(format!("{}user_list: - {}", prefix, user_id));
+            }
+        }
+    }
+
+    /// Replace top level fields in the config file, keeping it readable by the owner only
+    pub(crate) fn write_fields(path: &Path, fields: &[(&str, String)]) -> Result<(), String> {
+        let contents = Self::read_contents(path)?;
+        let mut edit = YamlEdit::new(&contents);
+        for (name, value) in fields {
+            edit.set(&[Segment::Key(name)], value)?;
+        }
+        Self::write_contents(path, &edit.finish()?)
+    }
+
+    fn write_contents(path: &Path, contents: &str) -> Result<(), String> {
+        let temp_path = path.with_extension("yml.tmp");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .map_err(|error| error.to_string())?;
+        file.write_all(contents.as_bytes())
+            .map_err(|error| error.to_string())?;
+        fs::rename(&temp_path, path).map_err(|error| error.to_string())?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|error| error.to_string())
+    }
+
+    /// Seal every secret in the config file with `new_key`, returns the fields written
+    pub(crate) fn reseal_file(
+        path: &Path,
+        old_key: &SecretKey,
+        new_key: &SecretKey,
+    ) -> Result<Vec<String>, String> {
+        let contents = Self::read_contents(path)?;
+        let config = Self::read_mapping(path, &contents)?;
+        let mut paths: Vec<Vec<Segment>> = SECRET_FIELDS
+            .iter()
+            .map(|name| vec![Segment::Key(name)])
+            .collect();
+        if let Some(Value::Sequence(tenants)) = config.get("tenants") {
+            for index in 0..tenants.len() {
+                paths.push(vec![
+                    Segment::Key("tenants"),
+                    Segment::Index(index),
+                    Segment::Key("app_secret"),
+                ]);
+            }
+        }
+        for name in EVENT_SECRET_FIELDS {
+            paths.push(vec![Segment::Key("events"), Segment::Key(name)]);
+        }
+
+        let mut edit = YamlEdit::new(&contents);
+        let mut fields: Vec<String> = Vec::new();
+        for path in paths {
+            let name = YamlEdit::name(&path);
+            let value = match YamlEdit::value_at(&config, &path) {
+                Some(value) if !value.trim().is_empty() => value,
+                _ => continue,
+            };
+            let plaintext = if SecretKey::is_encrypted(value) {
+                old_key
+                    .decrypt(value)
+                    .map_err(|error| format!("{}: {}", name, error))?
+            } else {
+                value.to_string()
+            };
+            edit.set(&path, &new_key.encrypt(&plaintext)?)?;
+            fields.push(name);
+        }
+        if !fields.is_empty() {
+            Self::write_contents(path, &edit.finish()?)?;
+        }
+        Ok(fields)
+    }
+
+    fn read_contents(path: &Path) -> Result<String, String> {
+        fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read config file {}: {}", path.display(), error))
+    }
+
+    fn read_mapping(path: &Path, contents: &str) -> Result<Mapping, String> {
+        if contents.trim().is_empty() {
+            return Ok(Mapping::new());
+        }
+        serde_yaml::from_str(contents)
+            .map_err(|error| format!("Unable to parse config file {}: {}", path.display(), error))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        if contents.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(contents).map_err(|error| error.to_string())
+    }
+
+    fn apply_env_overrides<F>(&mut self, var: F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(user_token) = var(ENV_USER_TOKEN) {
+            self.user_token = Some(user_token);
+        }
+        if let Some(user_token_file) = var(ENV_USER_TOKEN_FILE) {
+            self.user_token_file = Some(PathBuf::from(user_token_file));
+        }
+        if let Some(app_id) = var(ENV_APP_ID) {
+            self.app_id = app_id;
+        }
+        if let Some(app_secret) = var(ENV_APP_SECRET) {
+            self.app_secret = app_secret;
+        }
+        if let Some(user_list) = var(ENV_USER_LIST) {
+            self.user_list = user_list
+                .split(',')
+                .map(|user_id| user_id.trim().to_string())
+                .collect();
+        }
+        if self
+            .user_token
+            .as_deref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            self.user_token = None;
+        }
+    }
+
+    /// Read user token from `user_token_file` when it isn't set directly
+    fn read_user_token_file(&mut self) -> Result<(), String> {
+        let path = match (&self.user_token, &self.user_token_file) {
+            (None, Some(path)) => path,
+            _ => return Ok(()),
+        };
+        let user_token = fs::read_to_string(path).map_err(|error| {
+            format!(
+                "Unable to read user token file {}: {}",
+                path.display(),
+                error
+            )
+        })?;
+        let user_token = user_token.trim();
+        if user_token.is_empty() {
+            Err(format!("User token file {} is empty", path.display()))?
+        }
+        self.user_token = Some(user_token.to_string());
+        Ok(())
+    }
+
+    /// Decrypt `enc:` values in memory
+    fn decrypt_secrets(&mut self, key: Option<&SecretKey>) -> Result<(), String> {
+        let mut errors: Vec<String> = Vec::new();
+        let mut secrets = vec![
+            ("user_token".to_string(), self.user_token.as_mut()),
+            ("app_secret".to_string(), Some(&mut self.app_secret)),
+        ];
+        for (index, tenant) in self.tenants.iter_mut().enumerate() {
+            secrets.push((
+                format!("tenants[{}].app_secret", index),
+                Some(&mut tenant.app_secret),
+            ));
+        }
+        if let Some(events) = &mut self.events {
+            secrets.push((
+                "events.encrypt_key".to_string(),
+                events.encrypt_key.as_mut(),
+            ));
+            secrets.push((
+                "events.verification_token".to_string(),
+                events.verification_token.as_mut(),
+            ));
+        }
+        for (name, value) in secrets {
+            let value = match value {
+                Some(value) if SecretKey::is_encrypted(value) => value,
+                _ => continue,
+            };
+            match key.map(|key| key.decrypt(value)) {
+                Some(Ok(plaintext)) => *value = plaintext,
+                Some(Err(error)) => errors.push(format!("{}: {}", name, error)),
+                None => errors.push(format!(
+                    "{} is encrypted but no key is set, use --key-file, SIREN_KEY_FILE or SIREN_PASSPHRASE",
+                    name
+                )),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors
+                .iter()
+                .map(|error| format!("  - {}", error))
+                .collect::<Vec<String>>()
+                .join("\n"))
+        }
+    }
+
+    /// Check every field and report all problems at once
+    fn validate(&self) -> Result<(), String> {
+        let mut errors: Vec<String> = Vec::new();
+
+        if self.tenants.is_empty() || self.has_default_tenant() {
+            Self::validate_tenant("", &self.default_tenant(), &mut errors);
+        }
+        for (index, tenant) in self.tenants.iter().enumerate() {
+            Self::validate_tenant(&format!("tenants[{}].", index), tenant, &mut errors);
+        }
+        if self.status.live.mode != LiveMode::Off
+            && self.status.live.min_interval < LiveConfig::MIN_INTERVAL
+        {
+            errors.push(format!(
+                "status.live.min_interval must be at least {} seconds",
+                LiveConfig::MIN_INTERVAL
+            ));
+        }
+        if let Some(bot) = &self.bot {
+            if bot.chat_id.trim().is_empty() {
+                errors.push("bot.chat_id is missing".to_string());
+            }
+            if let Some(tenant) = &bot.tenant {
+                if !self.tenants().iter().any(|t| t.label() == tenant) {
+                    errors.push(format!(
+                        "bot.tenant \"{}\" is not a configured tenant",
+                        tenant
+                    ));
+                }
+            }
+        }
+        if let Some(events) = &self.events {
+            let is_set =
+                |value: &Option<String>| value.as_ref().is_some_and(|v| !v.trim().is_empty());
+            if !is_set(&events.encrypt_key) && !is_set(&events.verification_token) {
+                errors.push(
+                    "events needs encrypt_key or verification_token, otherwise anyone can send commands"
+                        .to_string(),
+                );
+            }
+        }
+        self.lyrics.ass.validate("lyrics.ass.", &mut errors);
+        if let Some(library) = &self.lyrics.library {
+            library.validate("lyrics.library.", &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors
+                .iter()
+                .map(|error| format!("  - {}", error))
+                .collect::<Vec<String>>()
+                .join("\n"))
+        }
+    }
+
+    /// Top level fields (empty `prefix`) can also be set from the environment
+    fn validate_tenant(prefix: &str, tenant: &TenantConfig, errors: &mut Vec<String>) {
+        let missing = |name: &str, env: &str| {
+            if prefix.is_empty() {
+                format!("{} is missing (or set {})", name, env)
+            } else {
+                format!("{}{} is missing", prefix, name)
+            }
+        };
+
+        if let Err(error) = feishu::base_url(&tenant.domain) {
+            errors.push(format!("{}domain {}", prefix, error));
+        }
+        if tenant.app_id.trim().is_empty() {
+            errors.push(missing("app_id", ENV_APP_ID));
+        } else if !tenant.app_id.starts_with("cli_") {
+            errors.push(format!(
+                "{}app_id \"{}\" is invalid, feishu app ids start with \"cli_\"",
+                prefix, tenant.app_id
+            ));
+        }
+        if tenant.app_secret.trim().is_empty() {
+            errors.push(missing("app_secret", ENV_APP_SECRET));
+        }
+        if tenant.user_list.is_empty() {
+            errors.push(missing("user_list", ENV_USER_LIST));
+        }
+        for (index, user_id) in tenant.user_list.iter().enumerate() {
+            if user_id.trim().is_empty() {
+                errors.push(format!("{}user_list[{}] is empty", prefix, index));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Config;
+    use crate::services::secret_handler::SecretKey;
+
+    #[test]
+    fn validate_reports_every_error() {
+        let config = Config::parse("app_id: foo\nuser_list:\n  - ou_1\n  - ''\n").unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("app_id \"foo\" is invalid"));
+        assert!(error.contains("app_secret is missing"));
+        assert!(error.contains("user_list[1] is empty"));
+        assert_eq!(3, error.lines().count());
+    }
+
+    #[test]
+    fn validate_lyrics_with_others() {
+        let config = Config::parse(
+            "app_id: foo\napp_secret: s\nuser_list: [ou_1]\nlyrics:\n  ass:\n    primary_colour: red\n  library:\n    path: ''\n",
+        )
+        .unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("app_id \"foo\" is invalid"));
+        assert!(error.contains("lyrics.ass.primary_colour \"red\" is invalid"));
+        assert!(error.contains("lyrics.library.path is empty"));
+        assert_eq!(3, error.lines().count());
+    }
+
+    #[test]
+    fn validate_events_keys() {
+        let config =
+            Config::parse("app_id: cli_1\napp_secret: s\nuser_list: [ou_1]\nevents: {}\n").unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("events needs encrypt_key or verification_token"));
+        let config = Config::parse(
+            "app_id: cli_1\napp_secret: s\nuser_list: [ou_1]\nevents:\n  encrypt_key: k\n",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_empty_config() {
+        let config = Config::parse("").unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("app_id is missing"));
+        assert!(error.contains("app_secret is missing"));
+        assert!(error.contains("user_list is missing"));
+    }
+
+    #[test]
+    fn env_overrides_config() {
+        let mut config =
+            Config::parse("user_token: ''\napp_id: cli_a\napp_secret: a\nuser_list: [ou_1]\n")
+                .unwrap();
+        config.apply_env_overrides(|key| match key {
+            "SIREN_APP_SECRET" => Some("secret".to_string()),
+            "SIREN_USER_LIST" => Some("ou_2, ou_3".to_string()),
+            _ => None,
+        });
+        assert_eq!(None, config.user_token);
+        assert_eq!("secret", config.app_secret);
+        assert_eq!(vec!["ou_2", "ou_3"], config.user_list);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn diff_hides_secrets() {
+        let old =
+            Config::parse("app_id: cli_a\napp_secret: old\nuser_list: [ou_1, ou_2]\n").unwrap();
+        let new =
+            Config::parse("app_id: cli_a\napp_secret: new\nuser_list: [ou_2, ou_3]\n").unwrap();
+        assert_eq!(
+            vec![
+                "app_secret changed",
+                "user_list: + ou_3",
+                "user_list: - ou_1"
+            ],
+            old.diff(&new)
+        );
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn decrypt_secrets_in_memory() {
+        let key = SecretKey::from_passphrase("passphrase");
+        let mut config = Config::parse(&format!(
+            "app_id: cli_a\napp_secret: {}\nuser_list: [ou_1]\n",
+            key.encrypt("plain_secret").unwrap()
+        ))
+        .unwrap();
+        assert!(config.clone().decrypt_secrets(None).is_err());
+        config.decrypt_secrets(Some(&key)).unwrap();
+        assert_eq!("plain_secret", config.app_secret);
+        assert!(!format!("{:?}", config).contains("plain_secret"));
+    }
+
+    #[test]
+    fn validate_tenants() {
+        let config = Config::parse(
+            "tenants:\n  - name: lark\n    domain: lark\n    app_id: cli_a\n    app_secret: a\n    user_list: [ou_1]\n  - domain: open example\n    app_id: cli_b\n",
+        )
+        .unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("tenants[1].domain \"open example\" is invalid"));
+        assert!(error.contains("tenants[1].app_secret is missing"));
+        assert!(error.contains("tenants[1].user_list is missing"));
+        assert_eq!(3, error.lines().count());
+        assert_eq!(2, config.tenants().len());
+    }
+}
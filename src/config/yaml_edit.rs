@@ -0,0 +1,325 @@
+use std::ops::Range;
+
+use serde_yaml::{Mapping, Value};
+
+/// Step from the top of the config file to a value, like `tenants`, `0`, `app_secret`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Replace scalars of a block style yaml file line by line, so comments and
+/// the order of keys are kept
+pub(crate) struct YamlEdit<'a> {
+    lines: Vec<&'a str>,
+    /// Replaced lines by index, appended lines after the original ones
+    replaced: Vec<(usize, String)>,
+    appended: Vec<String>,
+    checks: Vec<(Vec<Segment<'a>>, String)>,
+    newline: &'static str,
+    trailing_newline: bool,
+}
+
+impl<'a> YamlEdit<'a> {
+    pub(crate) fn new(contents: &'a str) -> Self {
+        Self {
+            lines: contents.lines().collect(),
+            replaced: Vec::new(),
+            appended: Vec::new(),
+            checks: Vec::new(),
+            newline: if contents.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+            trailing_newline: contents.is_empty() || contents.ends_with('\n'),
+        }
+    }
+
+    /// Set the scalar at `path`, a missing top level key is added at the end
+    pub(crate) fn set(&mut self, path: &[Segment<'a>], value: &str) -> Result<(), String> {
+        let name = Self::name(path);
+        let quoted = Self::quote(value)?;
+        match self.locate(path) {
+            Some((index, column)) => {
+                let key = match path.last() {
+                    Some(Segment::Key(key)) => key,
+                    _ => return Err(format!("{} is not a key", name)),
+                };
+                let line = self.line(index);
+                let line = Self::replace_value(&line, column, key, &quoted)
+                    .ok_or_else(|| format!("Unable to edit {}, write it as a single line", name))?;
+                self.replaced.retain(|(replaced, _)| *replaced != index);
+                self.replaced.push((index, line));
+            }
+            None => match path {
+                [Segment::Key(key)] => self.appended.push(format!("{}: {}", key, quoted)),
+                _ => return Err(format!("Unable to find {} in the config file", name)),
+            },
+        }
+        self.checks.push((path.to_vec(), value.to_string()));
+        Ok(())
+    }
+
+    /// The edited file, checked to parse to the values that were set
+    pub(crate) fn finish(self) -> Result<String, String> {
+        let mut lines: Vec<String> = (0..self.lines.len())
+            .map(|index| self.line(index))
+            .collect();
+        lines.extend(self.appended.iter().cloned());
+        let mut contents = lines.join(self.newline);
+        if self.trailing_newline && !contents.is_empty() {
+            contents.push_str(self.newline);
+        }
+
+        let config: Mapping = match contents.trim().is_empty() {
+            true => Mapping::new(),
+            false => serde_yaml::from_str(&contents).map_err(|error| error.to_string())?,
+        };
+        for (path, value) in &self.checks {
+            if Self::value_at(&config, path) != Some(value.as_str()) {
+                return Err(format!(
+                    "Unable to edit {} in place, the file is left unchanged",
+                    Self::name(path)
+                ));
+            }
+        }
+        Ok(contents)
+    }
+
+    fn line(&self, index: usize) -> String {
+        self.replaced
+            .iter()
+            .find(|(replaced, _)| *replaced == index)
+            .map(|(_, line)| line.clone())
+            .unwrap_or_else(|| self.lines[index].to_string())
+    }
+
+    /// Line of the key at `path` and the column the key starts at
+    fn locate(&self, path: &[Segment]) -> Option<(usize, usize)> {
+        let mut range = 0..self.lines.len();
+        let mut found = None;
+        for segment in path {
+            match segment {
+                Segment::Key(key) => {
+                    let (index, column) = self.find_key(range.clone(), key)?;
+                    range = index + 1..self.block_end(index + 1, range.end, column);
+                    found = Some((index, column));
+                }
+                Segment::Index(index) => {
+                    range = self.items(range).get(*index)?.clone();
+                    found = None;
+                }
+            }
+        }
+        found
+    }
+
+    /// `key:` at the column of the first key in `range`
+    fn find_key(&self, range: Range<usize>, key: &str) -> Option<(usize, usize)> {
+        let column = range
+            .clone()
+            .find_map(|index| Self::key_at(self.lines[index]))?
+            .0;
+        range
+            .into_iter()
+            .find_map(|index| match Self::key_at(self.lines[index]) {
+                Some((at, rest)) if at == column && Self::starts_with_key(rest, key) => {
+                    Some((index, column))
+                }
+                _ => None,
+            })
+    }
+
+    /// End of the value of a key at `column`, sequences may share the column of their key
+    fn block_end(&self, start: usize, end: usize, column: usize) -> usize {
+        (start..end)
+            .find(|index| {
+                let line = self.lines[*index];
+                let indent = Self::indent(line);
+                Self::is_content(line)
+                    && (indent < column
+                        || (indent == column && !line.trim_start().starts_with('-')))
+            })
+            .unwrap_or(end)
+    }
+
+    /// Lines of each item of the sequence in `range`
+    fn items(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let first = range
+            .clone()
+            .find(|index| Self::is_content(self.lines[*index]));
+        let dash = match first {
+            Some(index) if self.lines[index].trim_start().starts_with('-') => {
+                Self::indent(self.lines[index])
+            }
+            _ => return Vec::new(),
+        };
+        let starts: Vec<usize> = range
+            .clone()
+            .filter(|index| {
+                let line = self.lines[*index];
+                Self::is_content(line)
+                    && Self::indent(line) == dash
+                    && line.trim_start().starts_with('-')
+            })
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(number, start)| *start..starts.get(number + 1).copied().unwrap_or(range.end))
+            .collect()
+    }
+
+    /// Column of the key on a line and the text from there, `- ` counts as indentation
+    fn key_at(line: &str) -> Option<(usize, &str)> {
+        if !Self::is_content(line) {
+            return None;
+        }
+        let mut column = Self::indent(line);
+        let mut rest = &line[column..];
+        while let Some(item) = rest.strip_prefix('-') {
+            if !(item.is_empty() || item.starts_with(' ')) {
+                break;
+            }
+            let trimmed = item.trim_start_matches(' ');
+            column += 1 + item.len() - trimmed.len();
+            rest = trimmed;
+        }
+        Some((column, rest.trim_end_matches('\r'))).filter(|(_, rest)| !rest.is_empty())
+    }
+
+    fn starts_with_key(rest: &str, key: &str) -> bool {
+        let rest = match rest.strip_prefix(key) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        rest == ":" || rest.starts_with(": ") || rest.starts_with(":\t")
+    }
+
+    /// `key: value # comment` with a new value, `None` for multi-line and flow values
+    fn replace_value(line: &str, column: usize, key: &str, value: &str) -> Option<String> {
+        let (line, carriage) = match line.strip_suffix('\r') {
+            Some(line) => (line, "\r"),
+            None => (line, ""),
+        };
+        let split = column + key.len() + 1;
+        let old = line[split..].trim();
+        if old.starts_with(['|', '>', '{', '[', '&', '*', '!']) {
+            return None;
+        }
+        let comment = Self::comment(old)?;
+        let comment = match comment.is_empty() {
+            true => String::new(),
+            false => format!(" {}", comment),
+        };
+        Some(format!(
+            "{} {}{}{}",
+            &line[..split],
+            value,
+            comment,
+            carriage
+        ))
+    }
+
+    /// Comment after a scalar, `None` when a quoted scalar isn't closed on the line
+    fn comment(value: &str) -> Option<&str> {
+        let after = match value.chars().next() {
+            Some('"') => {
+                let mut escaped = false;
+                let close = value[1..].char_indices().find(|(_, c)| {
+                    let close = *c == '"' && !escaped;
+                    escaped = *c == '\\' && !escaped;
+                    close
+                })?;
+                &value[close.0 + 2..]
+            }
+            Some('\'') => &value[value[1..].find('\'')? + 2..],
+            _ => match value.find(" #") {
+                Some(index) => &value[index..],
+                None => "",
+            },
+        };
+        Some(after.trim()).filter(|after| after.is_empty() || after.starts_with('#'))
+    }
+
+    fn quote(value: &str) -> Result<String, String> {
+        let quoted = serde_yaml::to_string(&Value::String(value.to_string()))
+            .map_err(|error| error.to_string())?;
+        let quoted = quoted.trim_end();
+        match quoted.contains('\n') {
+            true => Ok(format!("{:?}", value)),
+            false => Ok(quoted.to_string()),
+        }
+    }
+
+    /// String at `path`, `None` when it is missing or not a string
+    pub(crate) fn value_at<'v>(config: &'v Mapping, path: &[Segment]) -> Option<&'v str> {
+        let mut value: Option<&Value> = None;
+        for segment in path {
+            value = match (segment, value) {
+                (Segment::Key(key), None) => config.get(*key),
+                (Segment::Key(key), Some(value)) => value.get(*key),
+                (Segment::Index(index), Some(value)) => value.get(*index),
+                (Segment::Index(_), None) => None,
+            };
+            value?;
+        }
+        value.and_then(Value::as_str)
+    }
+
+    /// Dotted name of a path for messages, like `tenants[0].app_secret`
+    pub(crate) fn name(path: &[Segment]) -> String {
+        let mut name = String::new();
+        for segment in path {
+            match segment {
+                Segment::Key(key) if name.is_empty() => name.push_str(key),
+                Segment::Key(key) => name.push_str(&format!(".{}", key)),
+                Segment::Index(index) => name.push_str(&format!("[{}]", index)),
+            }
+        }
+        name
+    }
+
+    fn is_content(line: &str) -> bool {
+        let line = line.trim();
+        !line.is_empty() && !line.starts_with('#') && line != "---"
+    }
+
+    fn indent(line: &str) -> usize {
+        line.len() - line.trim_start_matches(' ').len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::yaml_edit::{Segment, YamlEdit};
+
+    #[test]
+    fn edit_keeps_comments_and_order() {
+        let contents = "# Siren\napp_secret: old # from the console\ntenants:\n- name: a\n  app_secret: 'x' # first\n-   name: b\n    app_secret: \"y\"\nevents:\n  # keys\n  encrypt_key: z\nuser_list:\n  - ou_1\n";
+        let mut edit = YamlEdit::new(contents);
+        edit.set(&[Segment::Key("app_secret")], "enc:1").unwrap();
+        let second = [
+            Segment::Key("tenants"),
+            Segment::Index(1),
+            Segment::Key("app_secret"),
+        ];
+        edit.set(&second, "enc:2").unwrap();
+        edit.set(
+            &[Segment::Key("events"), Segment::Key("encrypt_key")],
+            "a #b",
+        )
+        .unwrap();
+        edit.set(&[Segment::Key("user_token")], "token").unwrap();
+        assert_eq!(
+            "# Siren\napp_secret: enc:1 # from the console\ntenants:\n- name: a\n  app_secret: 'x' # first\n-   name: b\n    app_secret: enc:2\nevents:\n  # keys\n  encrypt_key: 'a #b'\nuser_list:\n  - ou_1\nuser_token: token\n",
+            edit.finish().unwrap()
+        );
+
+        let mut edit = YamlEdit::new("events: {encrypt_key: z}\n");
+        let path = [Segment::Key("events"), Segment::Key("encrypt_key")];
+        assert!(edit.set(&path, "new").is_err());
+    }
+}
@@ -1,30 +1,133 @@
-use std::{
-    fs::File,
-    io::{stdin, Write},
-};
-
-use anyhow::Result;
-use axum::http::status;
-use reqwest::{header, Client};
+mod bot;
+mod contact;
+mod error;
+mod event;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::{header, Client, Method, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+use crate::config::{Config, TenantConfig};
+use crate::services::state_handler::StateFile;
+use crate::services::text_handler::Text;
 use crate::NowListening;
-use serde_yaml::Value;
-use std::io::Read;
+pub use bot::{BotConfig, FeishuBot};
+pub use error::FeishuError;
+pub use event::{ChatCommand, Event, EventConfig, SeenEvents};
+
+const FEISHU_BASE_URL: &str = "https://open.feishu.cn/open-apis";
+const LARK_BASE_URL: &str = "https://open.larksuite.com/open-apis";
+const MAX_RETRIES: u32 = 3;
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_millis(500);
+/// Fields patched when the play state changes and by live updates. The title keeps the
+/// status name so the status can be found again, clients show the localized titles
+const STATUS_FIELDS: [&str; 4] = ["ICON", "COLOR", "PRIORITY", "I18N_TITLE"];
+const LIVE_FIELDS: [&str; 1] = ["I18N_TITLE"];
+/// Fields reset to the idle template on close, the title too in case it was patched before
+const IDLE_FIELDS: [&str; 5] = ["ICON", "COLOR", "PRIORITY", "TITLE", "I18N_TITLE"];
+/// Refresh the tenant access token this long before feishu expires it
+const TOKEN_EXPIRE_MARGIN: u128 = 60 * 1000;
 
+/// Feishu apps of every configured tenant
 #[derive(Debug, Clone)]
+pub struct FeishuTenants {
+    tenants: Vec<FeishuRequest>,
+    status: StatusConfig,
+    bot: Option<FeishuBot>,
+    /// Cleared by `/siren pause sharing`, statuses are left alone until resumed
+    sharing: bool,
+    state_path: PathBuf,
+}
+
+#[derive(Clone)]
 pub struct FeishuRequest {
+    name: String,
+    base_url: String,
     app_id: String,
     app_secret: String,
     token: String,
     expire_time: u128,
     user_list: Vec<User>,
+    /// `user_list` as configured: open ids, emails, mobiles, departments or groups
+    user_entries: Vec<String>,
+    /// Open ids resolved from `user_entries`
+    user_cache: HashMap<String, Vec<String>>,
+    status: StatusConfig,
+    status_id: Option<String>,
+    state_path: PathBuf,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UserList {
-    user_list: Vec<User>,
+/// Status templates, selected by the current play state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusConfig {
+    /// Title of the system status owned by Siren, defaults to the idle title
+    pub name: Option<String>,
+    /// Seconds to wait after playback stops before closing the status
+    pub stop_grace_period: u64,
+    pub title_width: TitleWidth,
+    pub playing: StatusTemplate,
+    pub paused: StatusTemplate,
+    pub idle: StatusTemplate,
+    /// Keep the title following playback while playing
+    pub live: LiveConfig,
+    pub sync_setting: SyncSetting,
+}
+
+/// Display width limits of every status title, wide characters count as 2
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TitleWidth {
+    /// Width of `{name}`
+    pub name: Option<usize>,
+    /// Width of the whole title
+    pub title: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveMode {
+    Off,
+    /// Elapsed and total time
+    Progress,
+    /// The lyric line being sung, falls back to the progress title between lines
+    Lyrics,
+}
+
+/// Titles updated by a background task, with `{lyric}` as an extra placeholder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    pub mode: LiveMode,
+    /// Seconds between title updates, keeps us under feishu rate limits
+    pub min_interval: u64,
+    pub progress_title: String,
+    pub lyrics_title: String,
+}
+
+/// Feishu system status with `{name}`, `{artist}`, `{album}` and `{progress}` placeholders
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusTemplate {
+    pub title: String,
+    pub i18n_title: I18nTemplate,
+    pub icon_key: String,
+    pub color: String,
+    pub priority: u8,
+}
+
+/// Localized titles, falling back to `title` when unset
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct I18nTemplate {
+    pub en_us: Option<String>,
+    pub zh_cn: Option<String>,
+    pub ja_jp: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +136,29 @@ struct User {
     end_time: u128,
 }
 
+/// A user the status couldn't be opened or closed for
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFailure {
+    pub tenant: String,
+    pub user_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StatusPage {
+    #[serde(default)]
+    items: Vec<StatusItem>,
+    #[serde(default)]
+    has_more: bool,
+    page_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StatusItem {
+    system_status_id: String,
+    title: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Body {
     system_status: SystemStatus,
@@ -49,34 +175,494 @@ struct SystemStatus {
     sync_setting: SyncSetting,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct I18n {
-    en_us: String,
-    zh_cn: String,
-    ja_jp: String,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct I18n {
+    pub en_us: String,
+    pub zh_cn: String,
+    pub ja_jp: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SyncSetting {
-    is_open_by_default: bool,
-    title: String,
-    i18n_title: I18n,
-    explain: String,
-    i18n_explain: I18n,
+/// Automatic display setting of the status in the feishu client, sent when Siren creates it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncSetting {
+    pub is_open_by_default: bool,
+    pub title: String,
+    pub i18n_title: I18n,
+    pub explain: String,
+    pub i18n_explain: I18n,
+}
+
+impl Default for SyncSetting {
+    fn default() -> Self {
+        Self {
+            is_open_by_default: true,
+            title: "出差期间自动开启".to_string(),
+            i18n_title: I18n {
+                zh_cn: "出差期间自动开启".to_string(),
+                en_us: "Auto display Business Trip".to_string(),
+                ja_jp: "出張中に自動的にオンにする".to_string(),
+            },
+            explain: "出差审批通过后，将自动开启并优先展示该状态。".to_string(),
+            i18n_explain: I18n {
+                zh_cn: "出差审批通过后，该状态将自动开启并优先展示".to_string(),
+                en_us: "Auto-display after travel request is approved.".to_string(),
+                ja_jp: "申請が承認されると、このステータスが優先的に表示されます".to_string(),
+            },
+        }
+    }
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            stop_grace_period: 10,
+            title_width: TitleWidth::default(),
+            playing: StatusTemplate {
+                title: "🎵 {name}".to_string(),
+                i18n_title: I18nTemplate::default(),
+                ..StatusTemplate::default()
+            },
+            paused: StatusTemplate {
+                title: "⏸️ {name}".to_string(),
+                i18n_title: I18nTemplate::default(),
+                ..StatusTemplate::default()
+            },
+            idle: StatusTemplate::default(),
+            live: LiveConfig::default(),
+            sync_setting: SyncSetting::default(),
+        }
+    }
+}
+
+impl Default for TitleWidth {
+    fn default() -> Self {
+        Self {
+            name: Some(20),
+            title: None,
+        }
+    }
+}
+
+impl Default for LiveConfig {
+    fn default() -> Self {
+        Self {
+            mode: LiveMode::Off,
+            min_interval: 15,
+            progress_title: "🎵 {name} ▶ {progress}".to_string(),
+            lyrics_title: "🎵 {lyric}".to_string(),
+        }
+    }
+}
+
+impl Default for StatusTemplate {
+    fn default() -> Self {
+        Self {
+            title: "拖延症候群".to_string(),
+            i18n_title: I18nTemplate {
+                zh_cn: Some("拖延症候群".to_string()),
+                en_us: Some("Procrastination Syndrome".to_string()),
+                ja_jp: Some("先延ばし症候群".to_string()),
+            },
+            icon_key: "GeneralMoonRest".to_string(),
+            color: "VIOLET".to_string(),
+            priority: 1,
+        }
+    }
+}
+
+impl StatusConfig {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.idle.title)
+    }
+
+    fn select(&self, now_listening: &NowListening) -> &StatusTemplate {
+        if now_listening.is_playing {
+            &self.playing
+        } else if now_listening.name.is_some() {
+            &self.paused
+        } else {
+            &self.idle
+        }
+    }
+}
+
+impl LiveConfig {
+    /// Minimum `min_interval`, feishu allows few status updates per minute
+    pub(crate) const MIN_INTERVAL: u64 = 5;
+
+    /// Playing template with the live title, the same title is used for every language
+    fn template(&self, playing: &StatusTemplate, lyric: Option<&str>) -> StatusTemplate {
+        let title = match lyric {
+            Some(_) => &self.lyrics_title,
+            None => &self.progress_title,
+        };
+        StatusTemplate {
+            title: title.clone(),
+            i18n_title: I18nTemplate::default(),
+            ..playing.clone()
+        }
+    }
+}
+
+impl StatusTemplate {
+    /// Status with the title widths and sync setting of `status`
+    fn render(&self, now_listening: &NowListening, status: &StatusConfig) -> SystemStatus {
+        self.render_lyric(now_listening, "", status)
+    }
+
+    /// Render with `{lyric}` replaced by `lyric`
+    fn render_lyric(
+        &self,
+        now_listening: &NowListening,
+        lyric: &str,
+        status: &StatusConfig,
+    ) -> SystemStatus {
+        let width = status.title_width;
+        let i18n_title = |title: &Option<String>| {
+            Self::fill(
+                title.as_ref().unwrap_or(&self.title),
+                now_listening,
+                lyric,
+                width,
+            )
+        };
+        SystemStatus {
+            title: Self::fill(&self.title, now_listening, lyric, width),
+            i18n_title: I18n {
+                en_us: i18n_title(&self.i18n_title.en_us),
+                zh_cn: i18n_title(&self.i18n_title.zh_cn),
+                ja_jp: i18n_title(&self.i18n_title.ja_jp),
+            },
+            icon_key: self.icon_key.clone(),
+            color: self.color.clone(),
+            priority: self.priority,
+            sync_setting: status.sync_setting.clone(),
+        }
+    }
+
+    /// Replace placeholders and cut the result to `width`
+    fn fill(
+        template: &str,
+        now_listening: &NowListening,
+        lyric: &str,
+        width: TitleWidth,
+    ) -> String {
+        let name = now_listening.name.clone().unwrap_or_default();
+        let name = match width.name {
+            Some(width) => Text::truncate(&name, width),
+            None => name,
+        };
+        let artist = now_listening
+            .artist
+            .as_ref()
+            .map(|artist| artist.join(", "))
+            .unwrap_or_default();
+        let album = now_listening.album.clone().unwrap_or_default();
+        let title = template
+            .replace("{name}", &name)
+            .replace("{artist}", &artist)
+            .replace("{album}", &album)
+            .replace("{progress}", &Self::progress(now_listening))
+            .replace("{lyric}", lyric);
+        match width.title {
+            Some(width) => Text::truncate(&title, width),
+            None => title,
+        }
+    }
+
+    /// Elapsed and total time like `1:23/3:45`
+    fn progress(now_listening: &NowListening) -> String {
+        let duration = match now_listening.duration {
+            Some(duration) => duration as u128,
+            None => return String::new(),
+        };
+        let elapsed = match now_listening.start_time {
+            Some(start_time) => {
+                let now = chrono::Local::now().timestamp_millis() as u128;
+                now.saturating_sub(start_time).min(duration)
+            }
+            None => 0,
+        };
+        let format = |millis: u128| format!("{}:{:02}", millis / 60000, millis / 1000 % 60);
+        format!("{}/{}", format(elapsed), format(duration))
+    }
+}
+
+/// Open api address for `feishu`, `lark` or a private deployment like `open.example.com`
+pub(crate) fn base_url(domain: &str) -> Result<String, String> {
+    let domain = domain.trim().trim_end_matches('/');
+    match domain {
+        "" | "feishu" => return Ok(FEISHU_BASE_URL.to_string()),
+        "lark" => return Ok(LARK_BASE_URL.to_string()),
+        _ => (),
+    }
+    let url = if domain.contains("://") {
+        domain.to_string()
+    } else {
+        format!("https://{}", domain)
+    };
+    let host = url.split("://").nth(1).unwrap_or_default();
+    if !url.starts_with("http") || host.is_empty() || host.contains(char::is_whitespace) {
+        Err(format!(
+            "\"{}\" is invalid, use feishu, lark or the address of a private deployment",
+            domain
+        ))?
+    }
+    if url.ends_with("/open-apis") {
+        Ok(url)
+    } else {
+        Ok(format!("{}/open-apis", url))
+    }
+}
+
+impl FeishuTenants {
+    pub fn new(config: &Config, state_path: &Path) -> Self {
+        Self {
+            tenants: config
+                .tenants()
+                .iter()
+                .map(|tenant| FeishuRequest::new(tenant, &config.status, state_path))
+                .collect(),
+            status: config.status.clone(),
+            bot: config.bot.clone().map(FeishuBot::new),
+            sharing: true,
+            state_path: state_path.to_path_buf(),
+        }
+    }
+
+    /// Apply a reloaded config, keeping tokens and status ids of unchanged apps
+    pub fn apply_config(&mut self, config: &Config) {
+        let mut old_tenants = std::mem::take(&mut self.tenants);
+        self.status = config.status.clone();
+        self.bot = match (self.bot.take(), config.bot.clone()) {
+            (Some(mut bot), Some(bot_config)) => {
+                bot.apply_config(bot_config);
+                Some(bot)
+            }
+            (_, bot_config) => bot_config.map(FeishuBot::new),
+        };
+        for tenant in config.tenants() {
+            let base_url = base_url(&tenant.domain).unwrap_or_default();
+            let old_tenant = old_tenants.iter().position(|old_tenant| {
+                old_tenant.app_id == tenant.app_id && old_tenant.base_url == base_url
+            });
+            self.tenants.push(match old_tenant {
+                Some(index) => {
+                    let mut feishu_request = old_tenants.remove(index);
+                    feishu_request.apply_config(&tenant, &config.status);
+                    feishu_request
+                }
+                None => FeishuRequest::new(&tenant, &config.status, &self.state_path),
+            });
+        }
+    }
+
+    /// Resolve the users of every tenant, logging entries that couldn't be resolved
+    pub async fn resolve_users(&mut self) {
+        for feishu_request in &mut self.tenants {
+            for entry in feishu_request.resolve_users().await {
+                eprintln!("[{}] Unable to resolve user {}", feishu_request.name, entry);
+            }
+        }
+    }
+
+    /// Send the bot card for a new track, does nothing without a bot
+    pub async fn announce(&mut self, now_listening: &NowListening) -> Result<(), FeishuError> {
+        let bot = match &mut self.bot {
+            Some(bot) if self.sharing => bot,
+            _ => return Ok(()),
+        };
+        match Self::find_tenant(&mut self.tenants, bot.tenant()) {
+            Some(feishu_request) => bot.announce(feishu_request, now_listening).await,
+            None => Err(FeishuError::Config(
+                "No tenant found for the bot".to_string(),
+            )),
+        }
+    }
+
+    /// Reply to a chat message through the app of `tenant`, or the first one
+    pub async fn reply(
+        &mut self,
+        tenant: Option<&str>,
+        message_id: &str,
+        text: &str,
+    ) -> Result<(), FeishuError> {
+        match Self::find_tenant(&mut self.tenants, tenant) {
+            Some(feishu_request) => feishu_request.reply(message_id, text).await,
+            None => Err(FeishuError::Config(
+                "No tenant found for events".to_string(),
+            )),
+        }
+    }
+
+    fn find_tenant<'a>(
+        tenants: &'a mut [FeishuRequest],
+        name: Option<&str>,
+    ) -> Option<&'a mut FeishuRequest> {
+        match name {
+            Some(name) => tenants
+                .iter_mut()
+                .find(|feishu_request| feishu_request.name == name),
+            None => tenants.first_mut(),
+        }
+    }
+
+    pub fn is_sharing(&self) -> bool {
+        self.sharing
+    }
+
+    pub fn set_sharing(&mut self, sharing: bool) {
+        self.sharing = sharing;
+    }
+
+    pub fn stop_grace_period(&self) -> u64 {
+        self.status.stop_grace_period
+    }
+
+    pub fn live_interval(&self) -> Duration {
+        Duration::from_secs(self.status.live.min_interval.max(LiveConfig::MIN_INTERVAL))
+    }
+
+    /// Update the status of every tenant, returns the last error after trying them all
+    pub async fn update_status(&mut self, now_listening: NowListening) -> Result<(), FeishuError> {
+        if !self.sharing {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for feishu_request in &mut self.tenants {
+            if let Err(error) = feishu_request.update_status(now_listening.clone()).await {
+                eprintln!(
+                    "[{}] Unable to update feishu status: {}",
+                    feishu_request.name, error
+                );
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    /// Live mode, unless it is off or sharing is paused
+    pub fn live(&self) -> Option<LiveMode> {
+        match self.status.live.mode {
+            LiveMode::Off => None,
+            _ if !self.sharing => None,
+            mode => Some(mode),
+        }
+    }
+
+    pub async fn update_live(
+        &mut self,
+        now_listening: &NowListening,
+        lyric: Option<&str>,
+    ) -> Result<(), FeishuError> {
+        let mut result = Ok(());
+        for feishu_request in &mut self.tenants {
+            if let Err(error) = feishu_request.update_live(now_listening, lyric).await {
+                eprintln!(
+                    "[{}] Unable to update feishu live status: {}",
+                    feishu_request.name, error
+                );
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    pub async fn set_status(&mut self, time: u128) -> Result<Vec<UserFailure>, FeishuError> {
+        if !self.sharing {
+            return Ok(Vec::new());
+        }
+        let mut failures: Vec<UserFailure> = Vec::new();
+        let mut result = Ok(());
+        for feishu_request in &mut self.tenants {
+            match feishu_request.set_status(time).await {
+                Ok(tenant_failures) => failures.extend(tenant_failures),
+                Err(error) => {
+                    eprintln!(
+                        "[{}] Unable to set feishu status: {}",
+                        feishu_request.name, error
+                    );
+                    result = Err(error);
+                }
+            }
+        }
+        result.map(|_| failures)
+    }
+
+    pub async fn close_status(&mut self) -> Result<Vec<UserFailure>, FeishuError> {
+        let mut failures: Vec<UserFailure> = Vec::new();
+        let mut result = Ok(());
+        for feishu_request in &mut self.tenants {
+            match feishu_request.close_status().await {
+                Ok(tenant_failures) => failures.extend(tenant_failures),
+                Err(error) => {
+                    eprintln!(
+                        "[{}] Unable to close feishu status: {}",
+                        feishu_request.name, error
+                    );
+                    result = Err(error);
+                }
+            }
+        }
+        result.map(|_| failures)
+    }
+}
+
+impl fmt::Debug for FeishuRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeishuRequest")
+            .field("name", &self.name)
+            .field("base_url", &self.base_url)
+            .field("app_id", &self.app_id)
+            .field("app_secret", &"***")
+            .field("token", &"***")
+            .field("expire_time", &self.expire_time)
+            .field("user_list", &self.user_list)
+            .field("status", &self.status)
+            .field("status_id", &self.status_id)
+            .finish()
+    }
 }
 
 impl FeishuRequest {
-    pub fn new() -> Self {
-        let mut feishu_request = FeishuRequest {
-            app_id: String::new(),
-            app_secret: String::new(),
+    pub fn new(tenant: &TenantConfig, status: &StatusConfig, state_path: &Path) -> Self {
+        let user_cache = StateFile::load(state_path)
+            .feishu_users
+            .remove(&tenant.app_id)
+            .unwrap_or_default();
+        FeishuRequest {
+            name: tenant.label().to_string(),
+            base_url: base_url(&tenant.domain).unwrap_or_else(|_| FEISHU_BASE_URL.to_string()),
+            app_id: tenant.app_id.clone(),
+            app_secret: tenant.app_secret.clone(),
             token: String::new(),
             expire_time: 0,
-            user_list: Vec::new(),
+            user_list: Self::build_user_list(&tenant.user_list, &user_cache),
+            user_entries: tenant.user_list.clone(),
+            user_cache,
+            status: status.clone(),
+            status_id: None,
+            state_path: state_path.to_path_buf(),
+        }
+    }
+
+    /// Apply a reloaded config, keeping the cached token when the app is unchanged
+    fn apply_config(&mut self, tenant: &TenantConfig, status: &StatusConfig) {
+        if self.app_id != tenant.app_id || self.app_secret != tenant.app_secret {
+            self.token = String::new();
+            self.expire_time = 0;
+        }
+        if self.app_id != tenant.app_id || self.status.name() != status.name() {
+            self.status_id = None;
+        }
+        *self = FeishuRequest {
+            token: self.token.clone(),
+            expire_time: self.expire_time,
+            status_id: self.status_id.clone(),
+            ..FeishuRequest::new(tenant, status, &self.state_path)
         };
-        feishu_request.get_app_information();
-        feishu_request.refresh_token();
-        feishu_request
     }
 
     fn create_header(&self) -> header::HeaderMap {
@@ -95,92 +681,62 @@ impl FeishuRequest {
             .unwrap()
     }
 
-    pub async fn update_status(&self, now_listening: NowListening) {
-        let client = self.create_client();
-        let status_id = self.get_status().await;
-
-        let body_str = r#"{
-  "system_status": {
-    "title": "拖延症候群",
-    "i18n_title": {
-      "zh_cn": "拖延症候群",
-      "en_us": "Procrastination Syndrome",
-      "ja_jp": "先延ばし症候群"
-    },
-    "icon_key": "GeneralMoonRest",
-    "color": "VIOLET",
-    "priority": 1,
-    "sync_setting": {
-      "is_open_by_default": true,
-      "title": "出差期间自动开启",
-      "i18n_title": {
-        "zh_cn": "出差期间自动开启",
-        "en_us": "Auto display Business Trip",
-        "ja_jp": "出張中に自動的にオンにする"
-      },
-      "explain": "出差审批通过后，将自动开启并优先展示该状态。",
-      "i18n_explain": {
-        "zh_cn": "出差审批通过后，该状态将自动开启并优先展示",
-        "en_us": "Auto-display after travel request is approved.",
-        "ja_jp": "申請が承認されると、このステータスが優先的に表示されます"
-      }
-    }
-  },
-  "update_fields": [
-    "ICON",
-    "COLOR",
-    "TITLE",
-    "I18N_TITLE"
-  ]
-}"#;
-        let mut body: Body = serde_json::from_str(body_str).unwrap();
-
-        match now_listening.is_playing {
-            true => {
-                let name = now_listening.name.clone().unwrap();
-                let mut char_count = 0;
-                let mut truncated_name = String::new();
-
-                for c in name.chars() {
-                    if c.is_ascii() {
-                        char_count += 1;
-                    } else {
-                        char_count += 2;
-                    }
-
-                    if char_count > 20 {
-                        break;
-                    }
-
-                    truncated_name.push(c);
-                }
+    pub async fn update_status(&mut self, now_listening: NowListening) -> Result<(), FeishuError> {
+        let system_status = self
+            .status
+            .select(&now_listening)
+            .render(&now_listening, &self.status);
+        self.patch_status(system_status, &STATUS_FIELDS).await
+    }
 
-                body.system_status.title = format!("🎵 {}", truncated_name);
-                body.system_status.i18n_title.zh_cn =
-                    format!("🎵 {}", now_listening.name.clone().unwrap());
-                body.system_status.i18n_title.en_us =
-                    format!("🎵 {}", now_listening.name.clone().unwrap());
-                body.system_status.i18n_title.ja_jp = format!("🎵 {}", now_listening.name.unwrap());
-            }
-            _ => (),
-        }
+    /// Update only the title with the live progress or `lyric`
+    pub async fn update_live(
+        &mut self,
+        now_listening: &NowListening,
+        lyric: Option<&str>,
+    ) -> Result<(), FeishuError> {
+        let system_status = self
+            .status
+            .live
+            .template(&self.status.playing, lyric)
+            .render_lyric(now_listening, lyric.unwrap_or_default(), &self.status);
+        self.patch_status(system_status, &LIVE_FIELDS).await
+    }
 
-        let res = client
-            .patch(format!(
-                "https://open.feishu.cn/open-apis/personal_settings/v1/system_statuses/{}",
-                status_id
-            ))
-            .json(&body)
-            .send()
-            .await
-            .unwrap();
-        let res_string = res.text().await.unwrap();
-        println!("Feishu response: {:?}", res_string);
+    async fn patch_status(
+        &mut self,
+        system_status: SystemStatus,
+        update_fields: &[&str],
+    ) -> Result<(), FeishuError> {
+        let status_id = self.get_status().await?;
+        let body = self.patch_body(system_status, update_fields);
+
+        let res_json = self
+            .call(
+                Method::PATCH,
+                &format!("/personal_settings/v1/system_statuses/{}", status_id),
+                &[],
+                Some(&body),
+            )
+            .await?;
+        println!("Feishu response: {}", res_json);
+        Ok(())
+    }
+
+    /// Patch of `update_fields`, with the title left at the status name
+    fn patch_body(&self, mut system_status: SystemStatus, update_fields: &[&str]) -> Body {
+        system_status.title = self.status.name().to_string();
+        Body {
+            system_status,
+            update_fields: update_fields
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+        }
     }
 
-    pub async fn set_status(&mut self, time: u128) {
-        let client = self.create_client();
-        let status_id = self.get_status().await;
+    /// Open the status for every user until `time`, returns the users that failed
+    pub async fn set_status(&mut self, time: u128) -> Result<Vec<UserFailure>, FeishuError> {
         self.user_list = self
             .user_list
             .iter()
@@ -190,80 +746,491 @@ impl FeishuRequest {
             })
             .collect();
 
-        println!(
-            "{:#?}",
-            json!(UserList {
-                user_list: self.user_list.clone()
-            })
-            .to_string()
+        let user_list = self
+            .user_list
+            .iter()
+            .map(|user| (user.user_id.clone(), json!(user)))
+            .collect();
+        self.batch_users("batch_open", user_list).await
+    }
+
+    /// Reset the status to its name and the idle template, then close it for every
+    /// user, so feishu shows their own status again
+    pub async fn close_status(&mut self) -> Result<Vec<UserFailure>, FeishuError> {
+        let idle = self
+            .status
+            .idle
+            .render(&NowListening::default(), &self.status);
+        if let Err(error) = self.patch_status(idle, &IDLE_FIELDS).await {
+            eprintln!("[{}] Unable to reset feishu status: {}", self.name, error);
+        }
+        let user_list = self
+            .user_list
+            .iter()
+            .map(|user| (user.user_id.clone(), json!(user.user_id)))
+            .collect();
+        self.batch_users("batch_close", user_list).await
+    }
+
+    /// Run a batch action, retrying users one by one when the whole batch is rejected
+    async fn batch_users(
+        &mut self,
+        action: &str,
+        user_list: Vec<(String, serde_json::Value)>,
+    ) -> Result<Vec<UserFailure>, FeishuError> {
+        let status_id = self.get_status().await?;
+        let path = format!(
+            "/personal_settings/v1/system_statuses/{}/{}",
+            status_id, action
         );
+        let items: Vec<&serde_json::Value> = user_list.iter().map(|(_, item)| item).collect();
 
-        let res = client
-            .post(format!("https://open.feishu.cn/open-apis/personal_settings/v1/system_statuses/{}/batch_open", status_id))
-            .json(&UserList{user_list: self.user_list.clone()})
-            .send()
+        let error = match self
+            .call(
+                Method::POST,
+                &path,
+                &[],
+                Some(&json!({ "user_list": items })),
+            )
             .await
-            .unwrap();
-        println!("{:#?}", res.text().await.unwrap());
+        {
+            Ok(res_json) => return Ok(self.user_failures(&res_json)),
+            Err(error @ (FeishuError::Api { .. } | FeishuError::PermissionDenied { .. }))
+                if user_list.len() > 1 =>
+            {
+                error
+            }
+            Err(error) => return Err(error),
+        };
+
+        // A bad user id fails the whole batch, so find out which users are affected
+        eprintln!("{}, retrying {} for each user", error, action);
+        let mut failures: Vec<UserFailure> = Vec::new();
+        for (user_id, item) in user_list {
+            match self
+                .call(
+                    Method::POST,
+                    &path,
+                    &[],
+                    Some(&json!({ "user_list": [item] })),
+                )
+                .await
+            {
+                Ok(res_json) => failures.extend(self.user_failures(&res_json)),
+                Err(error) => failures.push(UserFailure {
+                    tenant: self.name.clone(),
+                    user_id,
+                    reason: error.to_string(),
+                }),
+            }
+        }
+        Ok(failures)
     }
 
-    async fn get_status(&self) -> String {
-        let client = self.create_client();
-        let res = client
-            .get("https://open.feishu.cn/open-apis/personal_settings/v1/system_statuses")
-            .send()
-            .await
-            .unwrap();
-        let res_json: serde_json::Value = res.json().await.unwrap();
-        println!("{:#?}", res_json);
-        let status_id = res_json["data"]["items"][0]["system_status_id"]
-            .as_str()
-            .unwrap()
-            .to_string();
-        status_id
+    /// Users in `result_list` whose result isn't a success
+    fn user_failures(&self, res_json: &serde_json::Value) -> Vec<UserFailure> {
+        res_json["data"]["result_list"]
+            .as_array()
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|result| {
+                        let reason = result["result"].as_str().unwrap_or_default();
+                        if reason.starts_with("success") {
+                            return None;
+                        }
+                        Some(UserFailure {
+                            tenant: self.name.clone(),
+                            user_id: result["user_id"].as_str().unwrap_or_default().to_string(),
+                            reason: reason.to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Find the system status owned by Siren, creating it when missing
+    async fn get_status(&mut self) -> Result<String, FeishuError> {
+        if let Some(status_id) = &self.status_id {
+            return Ok(status_id.clone());
+        }
+
+        let cached = Self::cached_status_id(
+            &StateFile::load(&self.state_path),
+            &self.app_id,
+            self.status.name(),
+        );
+        let statuses = self.list_statuses().await?;
+        let found = Self::find_status(&statuses, cached.as_deref(), self.status.name());
+        let status_id = match found {
+            Some(status_id) => status_id,
+            None => self.create_status().await?,
+        };
+
+        if let Err(error) = StateFile::update(&self.state_path, |state| {
+            Self::cache_status_id(state, &self.app_id, self.status.name(), &status_id)
+        }) {
+            eprintln!(
+                "Unable to save state file {}: {}",
+                self.state_path.display(),
+                error
+            );
+        }
+        self.status_id = Some(status_id.clone());
+        Ok(status_id)
+    }
+
+    /// The cached status, or the one titled `name`
+    fn find_status(statuses: &[StatusItem], cached: Option<&str>, name: &str) -> Option<String> {
+        statuses
+            .iter()
+            .find(|status| Some(status.system_status_id.as_str()) == cached)
+            .or_else(|| statuses.iter().find(|status| status.title == name))
+            .map(|status| status.system_status_id.clone())
+    }
+
+    /// Status id saved for the app, only while the status name is unchanged
+    fn cached_status_id(state: &StateFile, app_id: &str, name: &str) -> Option<String> {
+        state
+            .feishu_status_id
+            .get(&format!("{}/{}", app_id, name))
+            .cloned()
     }
 
-    pub async fn refresh_token(&mut self) {
+    /// Save the status id of the app, dropping ids saved under other names
+    fn cache_status_id(state: &mut StateFile, app_id: &str, name: &str, status_id: &str) {
+        let prefix = format!("{}/", app_id);
+        state
+            .feishu_status_id
+            .retain(|key, _| key != app_id && !key.starts_with(&prefix));
+        state
+            .feishu_status_id
+            .insert(format!("{}{}", prefix, name), status_id.to_string());
+    }
+
+    async fn list_statuses(&mut self) -> Result<Vec<StatusItem>, FeishuError> {
+        let mut statuses: Vec<StatusItem> = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("page_size", "50".to_string())];
+            if let Some(page_token) = &page_token {
+                query.push(("page_token", page_token.clone()));
+            }
+            let res_json = self
+                .call::<()>(
+                    Method::GET,
+                    "/personal_settings/v1/system_statuses",
+                    &query,
+                    None,
+                )
+                .await?;
+            let page: StatusPage = serde_json::from_value(res_json["data"].clone())
+                .map_err(|_| FeishuError::Http(format!("Invalid response: {}", res_json)))?;
+            statuses.extend(page.items);
+            match page.page_token {
+                Some(token) if page.has_more && !token.is_empty() => page_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(statuses)
+    }
+
+    async fn create_status(&mut self) -> Result<String, FeishuError> {
+        let mut system_status = self
+            .status
+            .idle
+            .render(&NowListening::default(), &self.status);
+        system_status.title = self.status.name().to_string();
+
+        let res_json = self
+            .call(
+                Method::POST,
+                "/personal_settings/v1/system_statuses",
+                &[],
+                Some(&system_status),
+            )
+            .await?;
+        println!("Created feishu status: {}", res_json);
+        match res_json["data"]["system_status"]["system_status_id"].as_str() {
+            Some(status_id) => Ok(status_id.to_string()),
+            None => Err(FeishuError::Http(format!("Invalid response: {}", res_json))),
+        }
+    }
+
+    /// Send a request, refreshing the token or backing off and retrying when feishu asks to
+    async fn call<T: Serialize + ?Sized>(
+        &mut self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<&T>,
+    ) -> Result<serde_json::Value, FeishuError> {
+        let mut attempt: u32 = 0;
+        loop {
+            self.refresh_token().await?;
+            let mut request = self
+                .create_client()
+                .request(method.clone(), format!("{}{}", self.base_url, path))
+                .query(query);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let error = match Self::parse_response(request.send().await?).await {
+                Ok(res_json) => return Ok(res_json),
+                Err(error) => error,
+            };
+            if attempt >= MAX_RETRIES {
+                return Err(error);
+            }
+            match error {
+                FeishuError::InvalidToken { .. } => {
+                    eprintln!("{}, refreshing token", error);
+                    self.expire_time = 0;
+                }
+                FeishuError::RateLimited { .. } => {
+                    let backoff = RATE_LIMIT_BACKOFF * 2u32.pow(attempt);
+                    eprintln!("{}, retrying in {:?}", error, backoff);
+                    tokio::time::sleep(backoff).await;
+                }
+                error => return Err(error),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Check the `{code, msg}` envelope and return the whole response
+    async fn parse_response(res: reqwest::Response) -> Result<serde_json::Value, FeishuError> {
+        let status = res.status();
+        let text = res.text().await?;
+        let res_json: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+        match res_json["code"].as_i64() {
+            Some(0) => Ok(res_json),
+            Some(code) => Err(FeishuError::from_code(
+                code,
+                res_json["msg"].as_str().unwrap_or_default().to_string(),
+            )),
+            None if status == StatusCode::TOO_MANY_REQUESTS => Err(FeishuError::RateLimited {
+                code: status.as_u16() as i64,
+                msg: text,
+            }),
+            None => Err(FeishuError::Http(format!("{}: {}", status, text))),
+        }
+    }
+
+    async fn refresh_token(&mut self) -> Result<(), FeishuError> {
         let now = chrono::Utc::now().timestamp_millis() as u128;
         if now < self.expire_time {
-            return;
+            return Ok(());
         }
         let client = Client::new();
         let res = client
-            .post("https://open.feishu.cn/open-apis/auth/v3/tenant_access_token/internal/")
+            .post(format!(
+                "{}/auth/v3/tenant_access_token/internal/",
+                self.base_url
+            ))
             .json(&serde_json::json!({
                 "app_id": self.app_id,
                 "app_secret": self.app_secret,
             }))
             .send()
-            .await
-            .unwrap();
-        let res_json: serde_json::Value = res.json().await.unwrap();
-        self.token = res_json["tenant_access_token"]
-            .as_str()
-            .unwrap()
-            .to_string();
+            .await?;
+        let res_json = Self::parse_response(res).await?;
+        self.token = match res_json["tenant_access_token"].as_str() {
+            Some(token) => token.to_string(),
+            None => Err(FeishuError::Http(format!("Invalid response: {}", res_json)))?,
+        };
         let now = chrono::Utc::now().timestamp_millis() as u128;
-        self.expire_time = (res_json["expire"].as_u64().unwrap() as u128) * 1000 + now;
+        let expire = res_json["expire"].as_u64().unwrap_or_default() as u128 * 1000;
+        self.expire_time = now + expire.saturating_sub(TOKEN_EXPIRE_MARGIN);
+        Ok(())
     }
+}
 
-    fn get_app_information(&mut self) {
-        let mut file = File::open("config.yml").expect("Unable to open config file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Unable to read config file");
-        let config: Value = serde_yaml::from_str(&contents).expect("Unable to parse config file");
+#[cfg(test)]
+mod test {
+    use crate::config::TenantConfig;
+    use crate::feishu::{
+        base_url, FeishuRequest, LiveConfig, StatusConfig, StatusItem, StatusTemplate, UserFailure,
+        IDLE_FIELDS, LIVE_FIELDS, STATUS_FIELDS,
+    };
+    use crate::services::state_handler::StateFile;
+    use crate::NowListening;
+    use std::path::Path;
 
-        self.app_id = config["app_id"].as_str().unwrap().to_string();
-        self.app_secret = config["app_secret"].as_str().unwrap().to_string();
-        self.user_list = config["user_list"]
-            .as_sequence()
-            .unwrap()
-            .iter()
-            .map(|x| User {
-                user_id: x.as_str().unwrap().to_string(),
-                end_time: 0,
-            })
-            .collect();
+    fn now_listening(is_playing: bool) -> NowListening {
+        NowListening {
+            is_playing,
+            name: Some("無答案".to_string()),
+            duration: Some(225000),
+            artist: Some(vec!["A".to_string(), "B".to_string()]),
+            album: Some("Album".to_string()),
+            album_cover: None,
+            url: None,
+            song_id: None,
+            isrc: None,
+            start_time: None,
+            credits: None,
+        }
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let template = StatusTemplate {
+            title: "{name} - {artist} ({album}) {progress}".to_string(),
+            ..StatusTemplate::default()
+        };
+        let status = template.render(&now_listening(true), &StatusConfig::default());
+        assert_eq!("無答案 - A, B (Album) 0:00/3:45", status.title);
+        assert_eq!("先延ばし症候群", status.i18n_title.ja_jp);
+    }
+
+    #[test]
+    fn render_live() {
+        let live = LiveConfig::default();
+        let playing = StatusTemplate::default();
+        let status = live.template(&playing, Some("歌詞")).render_lyric(
+            &now_listening(true),
+            "歌詞",
+            &StatusConfig::default(),
+        );
+        assert_eq!("🎵 歌詞", status.title);
+        assert_eq!("🎵 歌詞", status.i18n_title.en_us);
+        let status = live
+            .template(&playing, None)
+            .render(&now_listening(true), &StatusConfig::default());
+        assert_eq!("🎵 無答案 ▶ 0:00/3:45", status.title);
+    }
+
+    #[test]
+    fn select_template() {
+        let status = StatusConfig::default();
+        assert_eq!(status.playing, *status.select(&now_listening(true)));
+        assert_eq!(status.paused, *status.select(&now_listening(false)));
+        let mut stopped = now_listening(false);
+        stopped.name = None;
+        assert_eq!(status.idle, *status.select(&stopped));
+    }
+
+    #[test]
+    fn status_id_follows_name() {
+        let mut state = StateFile::default();
+        state
+            .feishu_status_id
+            .insert("cli_1".to_string(), "legacy".to_string());
+        FeishuRequest::cache_status_id(&mut state, "cli_1", "Old", "1");
+        FeishuRequest::cache_status_id(&mut state, "cli_2", "Old", "2");
+        assert_eq!(2, state.feishu_status_id.len());
+        assert_eq!(
+            Some("1".to_string()),
+            FeishuRequest::cached_status_id(&state, "cli_1", "Old")
+        );
+        assert_eq!(
+            None,
+            FeishuRequest::cached_status_id(&state, "cli_1", "New")
+        );
+        FeishuRequest::cache_status_id(&mut state, "cli_1", "New", "3");
+        assert_eq!(
+            None,
+            FeishuRequest::cached_status_id(&state, "cli_1", "Old")
+        );
+        assert_eq!(2, state.feishu_status_id.len());
+    }
+
+    #[test]
+    fn find_status_after_patch() {
+        let status = StatusConfig {
+            name: Some("Siren".to_string()),
+            ..StatusConfig::default()
+        };
+        let feishu_request =
+            FeishuRequest::new(&TenantConfig::default(), &status, Path::new("state.json"));
+        let mut item = StatusItem {
+            system_status_id: "s1".to_string(),
+            title: "Siren".to_string(),
+        };
+        let playing = status
+            .playing
+            .render(&now_listening(true), &StatusConfig::default());
+        let live = status
+            .live
+            .template(&status.playing, None)
+            .render(&now_listening(true), &StatusConfig::default());
+        for (system_status, fields) in [(playing, &STATUS_FIELDS[..]), (live, &LIVE_FIELDS[..])] {
+            let body = feishu_request.patch_body(system_status, fields);
+            assert!(body.system_status.i18n_title.zh_cn.starts_with("🎵 無答案"));
+            if body.update_fields.iter().any(|field| field == "TITLE") {
+                item.title = body.system_status.title;
+            }
+            assert_eq!(
+                Some("s1".to_string()),
+                FeishuRequest::find_status(&[item.clone()], None, status.name())
+            );
+        }
+
+        // Closing puts the name and the idle titles back
+        let idle = status
+            .idle
+            .render(&NowListening::default(), &StatusConfig::default());
+        let body = feishu_request.patch_body(idle, &IDLE_FIELDS);
+        assert!(body.update_fields.contains(&"TITLE".to_string()));
+        assert_eq!("Siren", body.system_status.title);
+        assert_eq!("拖延症候群", body.system_status.i18n_title.zh_cn);
+    }
+
+    #[test]
+    fn sync_setting_config() {
+        let status: StatusConfig =
+            serde_yaml::from_str("sync_setting:\n  is_open_by_default: false\n").unwrap();
+        assert!(!status.sync_setting.is_open_by_default);
+        assert_eq!("出差期间自动开启", status.sync_setting.title);
+        let system_status = status.idle.render(&NowListening::default(), &status);
+        assert!(!system_status.sync_setting.is_open_by_default);
+    }
+
+    #[test]
+    fn user_failures_test() {
+        let res_json = serde_json::json!({
+            "code": 0,
+            "data": {
+                "result_list": [
+                    { "user_id": "ou_1", "end_time": 1, "result": "success_show" },
+                    { "user_id": "ou_2", "end_time": 1, "result": "fail" }
+                ]
+            }
+        });
+        let tenant = TenantConfig {
+            name: Some("lark".to_string()),
+            ..TenantConfig::default()
+        };
+        let feishu_request =
+            FeishuRequest::new(&tenant, &StatusConfig::default(), Path::new("state.json"));
+        assert_eq!(
+            vec![UserFailure {
+                tenant: "lark".to_string(),
+                user_id: "ou_2".to_string(),
+                reason: "fail".to_string()
+            }],
+            feishu_request.user_failures(&res_json)
+        );
+    }
+
+    #[test]
+    fn base_url_test() {
+        assert_eq!("https://open.feishu.cn/open-apis", base_url("").unwrap());
+        assert_eq!(
+            "https://open.larksuite.com/open-apis",
+            base_url("lark").unwrap()
+        );
+        assert_eq!(
+            "https://open.example.com/open-apis",
+            base_url("open.example.com/").unwrap()
+        );
+        assert_eq!(
+            "http://10.0.0.1:8080/open-apis",
+            base_url("http://10.0.0.1:8080/open-apis").unwrap()
+        );
+        assert!(base_url("ftp://example.com").is_err());
+        assert!(base_url("open example").is_err());
     }
 }
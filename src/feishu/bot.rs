@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use reqwest::multipart::{Form, Part};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::feishu::{FeishuError, FeishuRequest};
+use crate::services::text_handler::Text;
+use crate::NowListening;
+
+/// Size of the artwork uploaded with the card
+const ARTWORK_SIZE: &str = "600";
+
+/// Group chat that gets a message card when a new track starts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BotConfig {
+    /// Chat id of the group, like `oc_...`
+    pub chat_id: String,
+    /// Tenant whose app sends the card, defaults to the first one
+    pub tenant: Option<String>,
+    /// Seconds after sending or editing a card during which new tracks edit it instead
+    pub min_interval: u64,
+    /// Display width of the card title, wide characters count as 2
+    pub title_width: Option<usize>,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            chat_id: String::new(),
+            tenant: None,
+            min_interval: 60,
+            title_width: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeishuBot {
+    config: BotConfig,
+    /// Message id of the last card and when it was last sent or edited
+    last_card: Option<(String, Instant)>,
+    /// Artwork url and image key of the last upload
+    artwork: Option<(String, String)>,
+}
+
+impl FeishuBot {
+    pub fn new(config: BotConfig) -> Self {
+        Self {
+            config,
+            last_card: None,
+            artwork: None,
+        }
+    }
+
+    /// Apply a reloaded config, forgetting the last card when the chat changes
+    pub(super) fn apply_config(&mut self, config: BotConfig) {
+        if self.config.chat_id != config.chat_id {
+            self.last_card = None;
+        }
+        self.config = config;
+    }
+
+    pub(super) fn tenant(&self) -> Option<&str> {
+        self.config.tenant.as_deref()
+    }
+
+    /// Send a card for the current track, or edit the last one if it is recent
+    pub(super) async fn announce(
+        &mut self,
+        feishu_request: &mut FeishuRequest,
+        now_listening: &NowListening,
+    ) -> Result<(), FeishuError> {
+        let image_key = match &now_listening.album_cover {
+            Some(artwork_url) => match self.image_key(feishu_request, artwork_url).await {
+                Ok(image_key) => Some(image_key),
+                Err(error) => {
+                    eprintln!("Unable to upload artwork: {}", error);
+                    None
+                }
+            },
+            None => None,
+        };
+        let content =
+            Self::card(now_listening, image_key.as_deref(), self.config.title_width).to_string();
+
+        let min_interval = Duration::from_secs(self.config.min_interval);
+        if let Some((message_id, updated_at)) = &mut self.last_card {
+            if updated_at.elapsed() < min_interval {
+                match feishu_request
+                    .call(
+                        Method::PATCH,
+                        &format!("/im/v1/messages/{}", message_id),
+                        &[],
+                        Some(&json!({ "content": content })),
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        *updated_at = Instant::now();
+                        return Ok(());
+                    }
+                    Err(error) => eprintln!("Unable to edit feishu card: {}", error),
+                }
+            }
+        }
+
+        let res_json = feishu_request
+            .call(
+                Method::POST,
+                "/im/v1/messages",
+                &[("receive_id_type", "chat_id".to_string())],
+                Some(&json!({
+                    "receive_id": self.config.chat_id,
+                    "msg_type": "interactive",
+                    "content": content,
+                })),
+            )
+            .await?;
+        match res_json["data"]["message_id"].as_str() {
+            Some(message_id) => {
+                self.last_card = Some((message_id.to_string(), Instant::now()));
+                Ok(())
+            }
+            None => Err(FeishuError::Http(format!("Invalid response: {}", res_json))),
+        }
+    }
+
+    /// Upload the artwork unless it is the same as the last one, like tracks of one album
+    async fn image_key(
+        &mut self,
+        feishu_request: &mut FeishuRequest,
+        artwork_url: &str,
+    ) -> Result<String, FeishuError> {
+        if let Some((url, image_key)) = &self.artwork {
+            if url == artwork_url {
+                return Ok(image_key.clone());
+            }
+        }
+        let url = artwork_url
+            .replace("{w}", ARTWORK_SIZE)
+            .replace("{h}", ARTWORK_SIZE);
+        let image = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+        let image_key = feishu_request.upload_image(image.to_vec()).await?;
+        self.artwork = Some((artwork_url.to_string(), image_key.clone()));
+        Ok(image_key)
+    }
+
+    fn card(
+        now_listening: &NowListening,
+        image_key: Option<&str>,
+        title_width: Option<usize>,
+    ) -> serde_json::Value {
+        let name = now_listening.name.clone().unwrap_or_default();
+        let title = format!("🎵 {}", name);
+        let title = match title_width {
+            Some(width) => Text::truncate(&title, width),
+            None => title,
+        };
+        let mut fields = Vec::new();
+        if let Some(artist) = &now_listening.artist {
+            fields.push(format!("**Artist**: {}", artist.join(", ")));
+        }
+        if let Some(album) = &now_listening.album {
+            fields.push(format!("**Album**: {}", album));
+        }
+
+        let mut elements = vec![json!({
+            "tag": "div",
+            "text": { "tag": "lark_md", "content": fields.join("\n") },
+        })];
+        if let Some(image_key) = image_key {
+            elements.push(json!({
+                "tag": "img",
+                "img_key": image_key,
+                "alt": { "tag": "plain_text", "content": name },
+            }));
+        }
+        if let Some(url) = &now_listening.url {
+            elements.push(json!({
+                "tag": "action",
+                "actions": [{
+                    "tag": "button",
+                    "text": { "tag": "plain_text", "content": "Listen on Apple Music" },
+                    "type": "primary",
+                    "url": url,
+                }],
+            }));
+        }
+
+        json!({
+            // Cards can only be edited with PATCH when they are shared
+            "config": { "wide_screen_mode": true, "update_multi": true },
+            "header": {
+                "title": { "tag": "plain_text", "content": title },
+                "template": "violet",
+            },
+            "elements": elements,
+        })
+    }
+}
+
+impl FeishuRequest {
+    /// Upload an image for messages, returns its image key
+    async fn upload_image(&mut self, image: Vec<u8>) -> Result<String, FeishuError> {
+        self.refresh_token().await?;
+        let form = Form::new()
+            .text("image_type", "message")
+            .part("image", Part::bytes(image).file_name("artwork.jpg"));
+        let res = self
+            .create_client()
+            .post(format!("{}/im/v1/images", self.base_url))
+            .multipart(form)
+            .send()
+            .await?;
+        let res_json = Self::parse_response(res).await?;
+        match res_json["data"]["image_key"].as_str() {
+            Some(image_key) => Ok(image_key.to_string()),
+            None => Err(FeishuError::Http(format!("Invalid response: {}", res_json))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::feishu::bot::FeishuBot;
+    use crate::NowListening;
+
+    #[test]
+    fn card_test() {
+        let now_listening = NowListening {
+            is_playing: true,
+            name: Some("Song".to_string()),
+            artist: Some(vec!["A".to_string(), "B".to_string()]),
+            album: Some("Album".to_string()),
+            ..NowListening::default()
+        };
+        let card = FeishuBot::card(&now_listening, None, None);
+        assert_eq!("🎵 Song", card["header"]["title"]["content"]);
+        assert_eq!(
+            "**Artist**: A, B\n**Album**: Album",
+            card["elements"][0]["text"]["content"]
+        );
+        assert_eq!(1, card["elements"].as_array().unwrap().len());
+        assert_eq!(true, card["config"]["update_multi"]);
+
+        let now_listening = NowListening {
+            url: Some("https://music.apple.com/song".to_string()),
+            ..now_listening
+        };
+        let card = FeishuBot::card(&now_listening, Some("img_1"), Some(6));
+        assert_eq!("🎵 So…", card["header"]["title"]["content"]);
+        assert_eq!("img_1", card["elements"][1]["img_key"]);
+        assert_eq!(
+            "https://music.apple.com/song",
+            card["elements"][2]["actions"][0]["url"]
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use reqwest::Method;
+use serde_json::json;
+
+use crate::feishu::{FeishuError, FeishuRequest, User};
+use crate::services::state_handler::StateFile;
+
+/// Most emails or mobiles batch_get_id accepts in one request
+const BATCH_GET_ID_LIMIT: usize = 50;
+/// Country code of mobiles written without one, feishu assumes the same
+const DEFAULT_COUNTRY_CODE: &str = "+86";
+
+/// An entry of `user_list` in the config
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum UserEntry {
+    /// Open id, used as is
+    Id(String),
+    Email(String),
+    Mobile(String),
+    /// Every member of a department, `department:<id>`
+    Department(String),
+    /// Every member of a user group, `group:<id>`
+    Group(String),
+}
+
+impl UserEntry {
+    pub(crate) fn parse(entry: &str) -> Self {
+        let entry = entry.trim();
+        let mobile: String = entry.chars().filter(|c| *c != ' ' && *c != '-').collect();
+        if let Some(department_id) = entry.strip_prefix("department:") {
+            UserEntry::Department(department_id.trim().to_string())
+        } else if let Some(group_id) = entry.strip_prefix("group:") {
+            UserEntry::Group(group_id.trim().to_string())
+        } else if entry.contains('@') {
+            UserEntry::Email(entry.to_lowercase())
+        } else if Self::is_mobile(&mobile) {
+            UserEntry::Mobile(Self::normalise_mobile(&mobile))
+        } else {
+            UserEntry::Id(entry.to_string())
+        }
+    }
+
+    fn is_mobile(mobile: &str) -> bool {
+        let digits = mobile.strip_prefix('+').unwrap_or(mobile);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// `+<country code><number>` without spaces or dashes
+    pub(crate) fn normalise_mobile(mobile: &str) -> String {
+        let mobile: String = mobile.chars().filter(|c| *c != ' ' && *c != '-').collect();
+        match mobile.starts_with('+') {
+            true => mobile,
+            false => format!("{}{}", DEFAULT_COUNTRY_CODE, mobile),
+        }
+    }
+
+    /// Key in the user cache, the same however the entry is spaced or cased
+    pub(crate) fn key(&self) -> String {
+        match self {
+            UserEntry::Id(id) | UserEntry::Email(id) | UserEntry::Mobile(id) => id.clone(),
+            UserEntry::Department(department_id) => format!("department:{}", department_id),
+            UserEntry::Group(group_id) => format!("group:{}", group_id),
+        }
+    }
+}
+
+impl FeishuRequest {
+    /// Open ids of every entry, taking resolved entries from `cache`
+    pub(super) fn build_user_list(
+        entries: &[String],
+        cache: &HashMap<String, Vec<String>>,
+    ) -> Vec<User> {
+        let mut user_ids: Vec<String> = Vec::new();
+        for entry in entries {
+            let resolved = match UserEntry::parse(entry) {
+                UserEntry::Id(user_id) => vec![user_id],
+                entry => cache.get(&entry.key()).cloned().unwrap_or_default(),
+            };
+            for user_id in resolved {
+                if !user_ids.contains(&user_id) {
+                    user_ids.push(user_id);
+                }
+            }
+        }
+        user_ids
+            .into_iter()
+            .map(|user_id| User {
+                user_id,
+                end_time: 0,
+            })
+            .collect()
+    }
+
+    /// Resolve emails, mobiles, departments and groups to open ids,
+    /// returns the entries that couldn't be resolved
+    pub async fn resolve_users(&mut self) -> Vec<String> {
+        let mut unresolved: Vec<String> = Vec::new();
+        let mut emails: Vec<String> = Vec::new();
+        let mut mobiles: Vec<String> = Vec::new();
+
+        for entry in self.user_entries.clone() {
+            let parsed = UserEntry::parse(&entry);
+            let members = match &parsed {
+                UserEntry::Id(_) => continue,
+                UserEntry::Email(email) => {
+                    if !emails.contains(email) {
+                        emails.push(email.clone());
+                    }
+                    continue;
+                }
+                UserEntry::Mobile(mobile) => {
+                    if !mobiles.contains(mobile) {
+                        mobiles.push(mobile.clone());
+                    }
+                    continue;
+                }
+                UserEntry::Department(department_id) => {
+                    self.department_members(department_id).await
+                }
+                UserEntry::Group(group_id) => self.group_members(group_id).await,
+            };
+            match members {
+                Ok(members) if !members.is_empty() => {
+                    self.user_cache.insert(parsed.key(), members);
+                }
+                Ok(_) => {
+                    self.user_cache.remove(&parsed.key());
+                    unresolved.push(format!("{}: no members", entry));
+                }
+                Err(error) => unresolved.push(format!("{}: {}", entry, error)),
+            }
+        }
+
+        for chunk in emails.chunks(BATCH_GET_ID_LIMIT) {
+            unresolved.extend(self.resolve_contacts("emails", chunk).await);
+        }
+        for chunk in mobiles.chunks(BATCH_GET_ID_LIMIT) {
+            unresolved.extend(self.resolve_contacts("mobiles", chunk).await);
+        }
+
+        self.user_list = Self::build_user_list(&self.user_entries, &self.user_cache);
+        if let Err(error) = StateFile::update(&self.state_path, |state| {
+            state
+                .feishu_users
+                .insert(self.app_id.clone(), self.user_cache.clone());
+        }) {
+            eprintln!(
+                "Unable to save state file {}: {}",
+                self.state_path.display(),
+                error
+            );
+        }
+        unresolved
+    }
+
+    /// Look up emails or mobiles, `kind` is the batch_get_id field to send,
+    /// `contacts` are cache keys, emails are compared in lowercase and mobiles normalised
+    async fn resolve_contacts(&mut self, kind: &str, contacts: &[String]) -> Vec<String> {
+        let res_json = match self
+            .call(
+                Method::POST,
+                "/contact/v3/users/batch_get_id",
+                &[("user_id_type", "open_id".to_string())],
+                Some(&json!({ kind: contacts })),
+            )
+            .await
+        {
+            Ok(res_json) => res_json,
+            Err(error) => {
+                return contacts
+                    .iter()
+                    .map(|contact| format!("{}: {}", contact, error))
+                    .collect()
+            }
+        };
+
+        let field = if kind == "emails" { "email" } else { "mobile" };
+        let found: HashMap<String, String> = res_json["data"]["user_list"]
+            .as_array()
+            .map(|users| {
+                users
+                    .iter()
+                    .filter_map(|user| {
+                        let contact = user[field].as_str()?;
+                        let contact = match field {
+                            "email" => contact.to_lowercase(),
+                            _ => UserEntry::normalise_mobile(contact),
+                        };
+                        Some((contact, user["user_id"].as_str()?.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut unresolved: Vec<String> = Vec::new();
+        for contact in contacts {
+            match found.get(contact) {
+                Some(user_id) => {
+                    self.user_cache
+                        .insert(contact.clone(), vec![user_id.clone()]);
+                }
+                None => {
+                    self.user_cache.remove(contact);
+                    unresolved.push(format!("{}: user not found", contact));
+                }
+            }
+        }
+        unresolved
+    }
+
+    async fn department_members(
+        &mut self,
+        department_id: &str,
+    ) -> Result<Vec<String>, FeishuError> {
+        let department_id_type = if department_id.starts_with("od-") {
+            "open_department_id"
+        } else {
+            "department_id"
+        };
+        self.list_ids(
+            "/contact/v3/users/find_by_department",
+            vec![
+                ("department_id", department_id.to_string()),
+                ("department_id_type", department_id_type.to_string()),
+                ("user_id_type", "open_id".to_string()),
+                ("page_size", "50".to_string()),
+            ],
+            "items",
+            "open_id",
+        )
+        .await
+    }
+
+    async fn group_members(&mut self, group_id: &str) -> Result<Vec<String>, FeishuError> {
+        self.list_ids(
+            &format!("/contact/v3/group/{}/member/simplelist", group_id),
+            vec![
+                ("member_id_type", "open_id".to_string()),
+                ("member_type", "user".to_string()),
+                ("page_size", "100".to_string()),
+            ],
+            "memberlist",
+            "member_id",
+        )
+        .await
+    }
+
+    /// Collect `id_key` of every item in `list_key` across all pages
+    async fn list_ids(
+        &mut self,
+        path: &str,
+        query: Vec<(&str, String)>,
+        list_key: &str,
+        id_key: &str,
+    ) -> Result<Vec<String>, FeishuError> {
+        let mut ids: Vec<String> = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = query.clone();
+            if let Some(page_token) = &page_token {
+                query.push(("page_token", page_token.clone()));
+            }
+            let res_json = self.call::<()>(Method::GET, path, &query, None).await?;
+            let data = &res_json["data"];
+            if let Some(items) = data[list_key].as_array() {
+                ids.extend(
+                    items
+                        .iter()
+                        .filter_map(|item| item[id_key].as_str().map(|id| id.to_string())),
+                );
+            }
+            match data["page_token"].as_str() {
+                Some(token) if data["has_more"].as_bool() == Some(true) && !token.is_empty() => {
+                    page_token = Some(token.to_string())
+                }
+                _ => break,
+            }
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::feishu::contact::UserEntry;
+    use crate::feishu::FeishuRequest;
+
+    #[test]
+    fn parse_user_entry() {
+        assert_eq!(UserEntry::Id("ou_1".to_string()), UserEntry::parse("ou_1"));
+        assert_eq!(
+            UserEntry::Email("a@example.com".to_string()),
+            UserEntry::parse(" a@example.com ")
+        );
+        assert_eq!(
+            UserEntry::Mobile("+8613000000000".to_string()),
+            UserEntry::parse("+8613000000000")
+        );
+        assert_eq!(
+            UserEntry::Mobile("+8613000000000".to_string()),
+            UserEntry::parse("13000000000")
+        );
+        assert_eq!(
+            UserEntry::Department("od-1".to_string()),
+            UserEntry::parse("department:od-1")
+        );
+        assert_eq!(
+            UserEntry::Group("g1".to_string()),
+            UserEntry::parse("group: g1")
+        );
+    }
+
+    #[test]
+    fn build_user_list_dedup() {
+        let entries = vec![
+            "ou_1".to_string(),
+            "a@example.com".to_string(),
+            "department:od-1".to_string(),
+            "b@example.com".to_string(),
+        ];
+        let cache = HashMap::from([
+            ("a@example.com".to_string(), vec!["ou_2".to_string()]),
+            (
+                "department:od-1".to_string(),
+                vec!["ou_1".to_string(), "ou_3".to_string()],
+            ),
+        ]);
+        let user_ids: Vec<String> = FeishuRequest::build_user_list(&entries, &cache)
+            .into_iter()
+            .map(|user| user.user_id)
+            .collect();
+        assert_eq!(vec!["ou_1", "ou_2", "ou_3"], user_ids);
+    }
+
+    #[test]
+    fn build_user_list_normalised_email() {
+        let entries = vec![" A@Example.com ".to_string(), "group: g1".to_string()];
+        let cache = HashMap::from([
+            (
+                UserEntry::parse("a@example.com").key(),
+                vec!["ou_1".to_string()],
+            ),
+            (UserEntry::parse("group:g1").key(), vec!["ou_2".to_string()]),
+        ]);
+        let user_ids: Vec<String> = FeishuRequest::build_user_list(&entries, &cache)
+            .into_iter()
+            .map(|user| user.user_id)
+            .collect();
+        assert_eq!(vec!["ou_1", "ou_2"], user_ids);
+    }
+
+    #[test]
+    fn build_user_list_normalised_mobile() {
+        let entries = vec![
+            " +86 130-0000-0000 ".to_string(),
+            "13000000000".to_string(),
+            "+1 555-0100".to_string(),
+        ];
+        let cache = HashMap::from([
+            (
+                UserEntry::parse("+8613000000000").key(),
+                vec!["ou_1".to_string()],
+            ),
+            (
+                UserEntry::parse("+15550100").key(),
+                vec!["ou_2".to_string()],
+            ),
+        ]);
+        let user_ids: Vec<String> = FeishuRequest::build_user_list(&entries, &cache)
+            .into_iter()
+            .map(|user| user.user_id)
+            .collect();
+        assert_eq!(vec!["ou_1", "ou_2"], user_ids);
+        assert_eq!(
+            UserEntry::parse("+86 130-0000-0000"),
+            UserEntry::parse("13000000000")
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// Tenant access token is missing, invalid or expired
+const INVALID_TOKEN_CODES: [i64; 4] = [99991661, 99991663, 99991668, 99991671];
+/// Request trigger frequency limit
+const RATE_LIMIT_CODES: [i64; 1] = [99991400];
+/// App scope not enabled, or no permission for the user
+const PERMISSION_DENIED_CODES: [i64; 3] = [99991672, 99991679, 99991401];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeishuError {
+    /// Network failure or a response that isn't a feishu envelope
+    Http(String),
+    /// Nothing was sent, the config doesn't allow the request, like a missing tenant
+    Config(String),
+    InvalidToken {
+        code: i64,
+        msg: String,
+    },
+    RateLimited {
+        code: i64,
+        msg: String,
+    },
+    PermissionDenied {
+        code: i64,
+        msg: String,
+    },
+    Api {
+        code: i64,
+        msg: String,
+    },
+}
+
+impl FeishuError {
+    /// Classify the `{code, msg}` envelope of a failed response
+    pub(crate) fn from_code(code: i64, msg: String) -> Self {
+        if INVALID_TOKEN_CODES.contains(&code) {
+            FeishuError::InvalidToken { code, msg }
+        } else if RATE_LIMIT_CODES.contains(&code) {
+            FeishuError::RateLimited { code, msg }
+        } else if PERMISSION_DENIED_CODES.contains(&code) {
+            FeishuError::PermissionDenied { code, msg }
+        } else {
+            FeishuError::Api { code, msg }
+        }
+    }
+}
+
+impl fmt::Display for FeishuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeishuError::Http(error) => write!(f, "Feishu request failed: {}", error),
+            FeishuError::Config(error) => write!(f, "Feishu config error: {}", error),
+            FeishuError::InvalidToken { code, msg } => {
+                write!(f, "Feishu token invalid ({}): {}", code, msg)
+            }
+            FeishuError::RateLimited { code, msg } => {
+                write!(f, "Feishu rate limited ({}): {}", code, msg)
+            }
+            FeishuError::PermissionDenied { code, msg } => {
+                write!(f, "Feishu permission denied ({}): {}", code, msg)
+            }
+            FeishuError::Api { code, msg } => write!(f, "Feishu error ({}): {}", code, msg),
+        }
+    }
+}
+
+impl std::error::Error for FeishuError {}
+
+impl From<reqwest::Error> for FeishuError {
+    fn from(error: reqwest::Error) -> Self {
+        FeishuError::Http(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::feishu::FeishuError;
+
+    #[test]
+    fn from_code_test() {
+        assert!(matches!(
+            FeishuError::from_code(99991663, String::new()),
+            FeishuError::InvalidToken { .. }
+        ));
+        assert!(matches!(
+            FeishuError::from_code(99991400, String::new()),
+            FeishuError::RateLimited { .. }
+        ));
+        assert!(matches!(
+            FeishuError::from_code(99991672, String::new()),
+            FeishuError::PermissionDenied { .. }
+        ));
+        assert_eq!(
+            "Feishu error (1): msg",
+            FeishuError::from_code(1, "msg".to_string()).to_string()
+        );
+        assert_eq!(
+            "Feishu config error: No tenant found for the bot",
+            FeishuError::Config("No tenant found for the bot".to_string()).to_string()
+        );
+    }
+}
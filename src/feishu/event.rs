@@ -0,0 +1,356 @@
+use aes_gcm::aes::Aes256;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use reqwest::{header, Method};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+
+use crate::feishu::{FeishuError, FeishuRequest};
+
+const IV_LEN: usize = 16;
+const COMMAND_PREFIX: &str = "/siren";
+/// Seconds a signed callback stays valid, older ones are treated as replays
+const MAX_EVENT_AGE: i64 = 5 * 60;
+/// Event ids remembered to drop callbacks feishu sends again
+const SEEN_EVENTS: usize = 256;
+
+/// Event subscription of the feishu app, used for chat commands
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventConfig {
+    /// Encrypt key from the event subscription page, also used to check signatures
+    pub encrypt_key: Option<String>,
+    pub verification_token: Option<String>,
+    /// Tenant whose app receives the events, defaults to the first one
+    pub tenant: Option<String>,
+}
+
+impl std::fmt::Debug for EventConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventConfig")
+            .field("encrypt_key", &self.encrypt_key.as_ref().map(|_| "***"))
+            .field(
+                "verification_token",
+                &self.verification_token.as_ref().map(|_| "***"),
+            )
+            .field("tenant", &self.tenant)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// URL verification, the challenge has to be sent back
+    Challenge(String),
+    /// Text message sent to the bot
+    Message {
+        event_id: String,
+        message_id: String,
+        text: String,
+    },
+    Ignored,
+}
+
+/// Ids of recent events, feishu sends a callback again when it isn't answered in time
+#[derive(Debug, Default)]
+pub struct SeenEvents {
+    ids: VecDeque<String>,
+}
+
+impl SeenEvents {
+    /// Remember `event_id`, false when it was seen before
+    pub fn insert(&mut self, event_id: &str) -> bool {
+        if self.ids.iter().any(|id| id == event_id) {
+            return false;
+        }
+        if self.ids.len() == SEEN_EVENTS {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(event_id.to_string());
+        true
+    }
+}
+
+/// `/siren <command>` sent to the bot
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    Now,
+    Lyrics,
+    /// Stop updating the feishu status until resumed
+    Pause,
+    Resume,
+    Help,
+}
+
+impl Event {
+    /// Decrypt and parse a callback body, `now` in unix seconds. Feishu doesn't sign
+    /// the url verification, so only event callbacks need a fresh signature
+    pub fn parse(
+        config: &EventConfig,
+        headers: &header::HeaderMap,
+        body: &[u8],
+        now: i64,
+    ) -> Result<Self, String> {
+        let mut event: serde_json::Value =
+            serde_json::from_slice(body).map_err(|error| error.to_string())?;
+        match (&config.encrypt_key, event["encrypt"].as_str()) {
+            (Some(encrypt_key), Some(encrypted)) => {
+                let plaintext = Self::decrypt(encrypt_key, encrypted)?;
+                event = serde_json::from_str(&plaintext).map_err(|error| error.to_string())?;
+            }
+            (None, Some(_)) => Err("event is encrypted but no encrypt_key is set".to_string())?,
+            (Some(_), None) => Err("event is not encrypted".to_string())?,
+            (None, None) => (),
+        }
+
+        if let Some(verification_token) = &config.verification_token {
+            let token = event["token"]
+                .as_str()
+                .or_else(|| event["header"]["token"].as_str());
+            if token != Some(verification_token.as_str()) {
+                Err("verification token mismatch".to_string())?
+            }
+        }
+
+        if event["type"] == "url_verification" {
+            return match event["challenge"].as_str() {
+                Some(challenge) => Ok(Event::Challenge(challenge.to_string())),
+                None => Err("challenge is missing".to_string()),
+            };
+        }
+        if let Some(encrypt_key) = &config.encrypt_key {
+            Self::check_signature(encrypt_key, headers, body, now)?;
+        }
+        if event["header"]["event_type"] != "im.message.receive_v1" {
+            return Ok(Event::Ignored);
+        }
+        let message = &event["event"]["message"];
+        if message["message_type"] != "text" {
+            return Ok(Event::Ignored);
+        }
+        let content: serde_json::Value =
+            serde_json::from_str(message["content"].as_str().unwrap_or_default())
+                .map_err(|error| error.to_string())?;
+        match (message["message_id"].as_str(), content["text"].as_str()) {
+            (Some(message_id), Some(text)) => Ok(Event::Message {
+                event_id: event["header"]["event_id"]
+                    .as_str()
+                    .unwrap_or(message_id)
+                    .to_string(),
+                message_id: message_id.to_string(),
+                text: text.to_string(),
+            }),
+            _ => Ok(Event::Ignored),
+        }
+    }
+
+    /// Signature of the body with a timestamp within `MAX_EVENT_AGE` of `now`
+    fn check_signature(
+        encrypt_key: &str,
+        headers: &header::HeaderMap,
+        body: &[u8],
+        now: i64,
+    ) -> Result<(), String> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(format!("{} is missing", name))
+        };
+        let signature = header("X-Lark-Signature")?;
+        let timestamp = header("X-Lark-Request-Timestamp")?;
+        let nonce = header("X-Lark-Request-Nonce")?;
+        let expected = Self::signature(encrypt_key, timestamp, nonce, body);
+        if !Self::same(&expected, signature) {
+            Err("signature mismatch".to_string())?
+        }
+        match timestamp.parse::<i64>() {
+            Ok(timestamp) if (now - timestamp).abs() <= MAX_EVENT_AGE => Ok(()),
+            _ => Err(format!("timestamp {} is stale", timestamp)),
+        }
+    }
+
+    /// Hex sha256 of `timestamp + nonce + encrypt_key + body`
+    fn signature(encrypt_key: &str, timestamp: &str, nonce: &str, body: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(timestamp.as_bytes());
+        hasher.update(nonce.as_bytes());
+        hasher.update(encrypt_key.as_bytes());
+        hasher.update(body);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Compare without returning early, so timing doesn't tell how much of a signature matched
+    fn same(expected: &str, actual: &str) -> bool {
+        expected.len() == actual.len()
+            && expected
+                .bytes()
+                .zip(actual.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+
+    /// AES-256-CBC with the sha256 of the encrypt key, the iv is the first block
+    fn decrypt(encrypt_key: &str, encrypted: &str) -> Result<String, String> {
+        let data = STANDARD
+            .decode(encrypted)
+            .map_err(|error| format!("invalid encrypted event: {}", error))?;
+        if data.len() <= IV_LEN {
+            Err("invalid encrypted event: too short".to_string())?
+        }
+        let (iv, ciphertext) = data.split_at(IV_LEN);
+        let key = Sha256::digest(encrypt_key.as_bytes());
+        let plaintext = cbc::Decryptor::<Aes256>::new_from_slices(&key, iv)
+            .map_err(|error| error.to_string())?
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .map_err(|_| "unable to decrypt event, wrong encrypt_key?".to_string())?;
+        String::from_utf8(plaintext).map_err(|error| error.to_string())
+    }
+}
+
+impl ChatCommand {
+    /// Parse `/siren <command>`, ignoring mentions like `@_user_1`
+    pub fn parse(text: &str) -> Option<Self> {
+        let words: Vec<&str> = text
+            .split_whitespace()
+            .filter(|word| !word.starts_with("@_user_"))
+            .collect();
+        match words.split_first() {
+            Some((&COMMAND_PREFIX, args)) => Some(match args.first().copied() {
+                Some("now") => ChatCommand::Now,
+                Some("lyrics") => ChatCommand::Lyrics,
+                Some("pause") => ChatCommand::Pause,
+                Some("resume") => ChatCommand::Resume,
+                _ => ChatCommand::Help,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn usage() -> &'static str {
+        "/siren now - current track\n/siren lyrics - lyrics of the current track\n/siren pause sharing - stop updating the status\n/siren resume sharing - update the status again"
+    }
+}
+
+impl FeishuRequest {
+    /// Reply to a message with plain text
+    pub(super) async fn reply(&mut self, message_id: &str, text: &str) -> Result<(), FeishuError> {
+        self.call(
+            Method::POST,
+            &format!("/im/v1/messages/{}/reply", message_id),
+            &[],
+            Some(&json!({
+                "msg_type": "text",
+                "content": json!({ "text": text }).to_string(),
+            })),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aes_gcm::aes::Aes256;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+    use reqwest::header::HeaderMap;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    use crate::feishu::event::{ChatCommand, Event, EventConfig, SeenEvents, SEEN_EVENTS};
+
+    fn encrypt(encrypt_key: &str, plaintext: &str) -> String {
+        let key = Sha256::digest(encrypt_key.as_bytes());
+        let iv = [7u8; 16];
+        let ciphertext = cbc::Encryptor::<Aes256>::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+        STANDARD.encode([iv.to_vec(), ciphertext].concat())
+    }
+
+    #[test]
+    fn parse_encrypted_event() {
+        let config = EventConfig {
+            encrypt_key: Some("key".to_string()),
+            verification_token: Some("token".to_string()),
+            tenant: None,
+        };
+        let message = json!({
+            "schema": "2.0",
+            "header": { "event_id": "ev_1", "event_type": "im.message.receive_v1", "token": "token" },
+            "event": { "message": {
+                "message_id": "om_1",
+                "message_type": "text",
+                "content": json!({ "text": "@_user_1 /siren now" }).to_string(),
+            }},
+        });
+        let body = json!({ "encrypt": encrypt("key", &message.to_string()) }).to_string();
+
+        let now = 1_700_000_000;
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Lark-Request-Timestamp", now.into());
+        headers.insert("X-Lark-Request-Nonce", "n".parse().unwrap());
+        let signature = Event::signature("key", &now.to_string(), "n", body.as_bytes());
+        headers.insert("X-Lark-Signature", signature.parse().unwrap());
+        assert_eq!(
+            Event::Message {
+                event_id: "ev_1".to_string(),
+                message_id: "om_1".to_string(),
+                text: "@_user_1 /siren now".to_string(),
+            },
+            Event::parse(&config, &headers, body.as_bytes(), now + 60).unwrap()
+        );
+        assert_eq!(
+            Err(format!("timestamp {} is stale", now)),
+            Event::parse(&config, &headers, body.as_bytes(), now + 600)
+        );
+
+        headers.insert("X-Lark-Signature", "bad".parse().unwrap());
+        assert!(Event::parse(&config, &headers, body.as_bytes(), now).is_err());
+
+        headers.remove("X-Lark-Signature");
+        assert_eq!(
+            Err("X-Lark-Signature is missing".to_string()),
+            Event::parse(&config, &headers, body.as_bytes(), now)
+        );
+
+        // Feishu doesn't sign the url verification
+        let challenge = json!({ "type": "url_verification", "challenge": "c", "token": "token" });
+        let body = json!({ "encrypt": encrypt("key", &challenge.to_string()) }).to_string();
+        assert_eq!(
+            Event::Challenge("c".to_string()),
+            Event::parse(&config, &HeaderMap::new(), body.as_bytes(), now).unwrap()
+        );
+        let challenge = json!({ "type": "url_verification", "challenge": "c", "token": "bad" });
+        let body = json!({ "encrypt": encrypt("key", &challenge.to_string()) }).to_string();
+        assert!(Event::parse(&config, &HeaderMap::new(), body.as_bytes(), now).is_err());
+    }
+
+    #[test]
+    fn seen_events() {
+        let mut seen = SeenEvents::default();
+        assert!(seen.insert("ev_1"));
+        assert!(!seen.insert("ev_1"));
+        for index in 0..SEEN_EVENTS {
+            seen.insert(&index.to_string());
+        }
+        assert!(seen.insert("ev_1"));
+    }
+
+    #[test]
+    fn parse_chat_command() {
+        assert_eq!(
+            Some(ChatCommand::Now),
+            ChatCommand::parse("@_user_1 /siren now")
+        );
+        assert_eq!(
+            Some(ChatCommand::Pause),
+            ChatCommand::parse("/siren pause sharing")
+        );
+        assert_eq!(Some(ChatCommand::Help), ChatCommand::parse("/siren"));
+        assert_eq!(None, ChatCommand::parse("hello /siren now"));
+    }
+}
@@ -1,15 +1,31 @@
+mod config;
 mod feishu;
 mod models;
 mod services;
 
+use config::Config;
 use core::str;
+use models::lyric_json::{Credits, LyricsJSON};
+use models::timestamp::TimeFormat;
 use services::apple_music_url::Request;
-use std::sync::Arc;
+use services::export_handler::{
+    AssConfig, Export, ExportOptions, LrcOptions, LrcVariant, LyricsFormat,
+};
+use services::import_handler::{Import, ImportFormat};
+use services::library_handler::{Library, SongKeys};
+use services::response_handler::{Response as ResponseHandler, TimedLine};
+use services::secret_handler::SecretKey;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use axum::{
     self,
-    extract::{FromRef, Json, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{FromRef, Json, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
@@ -18,11 +34,23 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use services::token_handler::Token;
 use structopt::StructOpt;
-use tokio::{net::TcpListener, sync::Mutex};
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::Mutex,
+    task::JoinHandle,
+};
 
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+const ACCESS_TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const ACCESS_TOKEN_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Response header with the sync level of the lyrics, for formats that can't carry it
+const LYRICS_SYNC_HEADER: &str = "x-lyrics-sync";
+/// Keep the feishu status open this long when the track duration is unknown
+const DEFAULT_STATUS_DURATION: u128 = 10 * 60 * 1000;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct NowListening {
     is_playing: bool,
     name: Option<String>,
@@ -30,7 +58,14 @@ pub struct NowListening {
     artist: Option<Vec<String>>,
     album: Option<String>,
     album_cover: Option<String>,
+    /// Apple Music page of the song
+    url: Option<String>,
+    song_id: Option<String>,
+    /// Recording code of the song, one of the names it can have in the lyrics library
+    isrc: Option<String>,
     start_time: Option<u128>,
+    /// Songwriters from the lyrics, looked up when the track starts
+    credits: Option<Credits>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -40,6 +75,32 @@ enum PlayStatus {
     Stopped,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct LyricsQuery {
+    /// Apple Music song url or id, defaults to the current track
+    song: Option<String>,
+    #[serde(default)]
+    format: LyricsFormat,
+    #[serde(default)]
+    time_format: TimeFormat,
+    /// Keep the spaces between words of the plain lyrics
+    #[serde(default)]
+    space: bool,
+    /// Language of the translation, defaults to the first available one
+    lang: Option<String>,
+    #[serde(default)]
+    lrc: LrcVariant,
+    /// Milliseconds written to the `[offset:]` tag of lrc output
+    offset: Option<i64>,
+    /// Add the translation after every line of lrc output
+    #[serde(default)]
+    translation: bool,
+    /// WebVTT cue settings for background vocals, like `line:0`
+    background_settings: Option<String>,
+    /// Format of lyrics posted for conversion, detected when missing
+    from: Option<ImportFormat>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct AutoUpdate {
     play_status: PlayStatus,
@@ -51,13 +112,56 @@ struct AutoUpdate {
 struct Input {
     #[structopt(short, long, default_value = "0.0.0.0:3939")]
     address: String,
+    #[structopt(short, long, parse(from_os_str), default_value = "config.yml")]
+    config: PathBuf,
+    /// Fail instead of prompting for missing values
+    #[structopt(long)]
+    non_interactive: bool,
+    /// Key file used for `enc:` values, otherwise SIREN_KEY_FILE or SIREN_PASSPHRASE
+    #[structopt(long, parse(from_os_str))]
+    key_file: Option<PathBuf>,
+    /// File used to keep cached tokens between restarts
+    #[structopt(long, parse(from_os_str), default_value = "state.json")]
+    state: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(structopt::StructOpt)]
+enum Command {
+    /// Encrypt user_token, app_secret and event keys in the config file
+    Encrypt,
+    /// Re-encrypt secrets in the config file with a new key
+    Rotate {
+        /// New key file, otherwise SIREN_NEW_PASSPHRASE is used
+        #[structopt(long, parse(from_os_str))]
+        new_key_file: Option<PathBuf>,
+    },
+    /// Convert a lyrics file between lrc, srt, vtt, ass, TTML and json
+    Convert {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Format of the input file, detected when missing
+        #[structopt(long)]
+        from: Option<ImportFormat>,
+        #[structopt(long, default_value = "lrc")]
+        to: LyricsFormat,
+        /// File name without extension, otherwise written to stdout
+        #[structopt(short, long)]
+        output: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone)]
 struct ShareState {
     now_listening: Arc<Mutex<NowListening>>,
     request: Arc<Mutex<Request>>,
-    feishu_request: Arc<Mutex<feishu::FeishuRequest>>,
+    feishu_request: Arc<Mutex<feishu::FeishuTenants>>,
+    config: Arc<Mutex<Config>>,
+    /// Pending close of the feishu status after playback stopped
+    stop_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Chat command events already handled
+    seen_events: Arc<Mutex<feishu::SeenEvents>>,
 }
 
 impl FromRef<ShareState> for Arc<Mutex<NowListening>> {
@@ -72,7 +176,7 @@ impl FromRef<ShareState> for Arc<Mutex<Request>> {
     }
 }
 
-impl FromRef<ShareState> for Arc<Mutex<feishu::FeishuRequest>> {
+impl FromRef<ShareState> for Arc<Mutex<feishu::FeishuTenants>> {
     fn from_ref(share_state: &ShareState) -> Self {
         share_state.feishu_request.clone()
     }
@@ -80,7 +184,36 @@ impl FromRef<ShareState> for Arc<Mutex<feishu::FeishuRequest>> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let listener = TcpListener::bind(Input::from_args().address).await?;
+    dotenv::dotenv().ok();
+    let input = Input::from_args();
+
+    let key = match SecretKey::from_env(input.key_file.as_deref()) {
+        Ok(key) => key,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(command) = input.command {
+        if let Err(error) = run_command(command, &input.config, key.as_ref()) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    println!("Loading config...");
+    let mut config = match Config::load(&input.config, key.as_ref()) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    println!("Config: Done!");
+
+    let listener = TcpListener::bind(input.address).await?;
     let now_listening = Arc::new(Mutex::new(NowListening {
         is_playing: false,
         name: None,
@@ -88,24 +221,44 @@ async fn main() -> Result<()> {
         artist: None,
         album: None,
         album_cover: None,
+        url: None,
+        song_id: None,
+        isrc: None,
         start_time: None,
+        credits: None,
     }));
 
     println!("Loading apple music access token...");
-    let authorization = Token::get_access_token().await.unwrap();
+    let access_token = match Token::get_cached_access_token(&input.state).await {
+        Ok(access_token) => access_token,
+        Err(error) => {
+            eprintln!("Unable to load access token: {}", error);
+            std::process::exit(1);
+        }
+    };
     println!("Access token: Done!");
 
     println!("Loading user token...");
-    let user_token = Token::get_user_token();
+    let user_token =
+        match Token::get_user_token(&config, &input.config, key.as_ref(), !input.non_interactive) {
+            Ok(user_token) => user_token,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        };
+    config.user_token = Some(user_token.clone());
     println!("User token: Done!");
-    let mut request = Request::new(authorization, user_token);
+    let mut request = Request::new(access_token.token, user_token);
 
     println!("Get user storefront...");
     request.get_user_storefront().await;
     println!("User storefront: Done!");
 
     println!("Loading feishu app information...");
-    let feishu_request = Arc::new(Mutex::new(feishu::FeishuRequest::new()));
+    let mut feishu_request = feishu::FeishuTenants::new(&config, &input.state);
+    feishu_request.resolve_users().await;
+    let feishu_request = Arc::new(Mutex::new(feishu_request));
     println!("Feishu app information: Done!");
 
     let request = Arc::new(Mutex::new(request));
@@ -114,18 +267,243 @@ async fn main() -> Result<()> {
         now_listening: now_listening.clone(),
         request,
         feishu_request,
+        config: Arc::new(Mutex::new(config)),
+        stop_task: Arc::new(Mutex::new(None)),
+        seen_events: Arc::new(Mutex::new(feishu::SeenEvents::default())),
     };
 
+    tokio::spawn(watch_config(input.config, key, state.clone()));
+    tokio::spawn(live_status(state.clone()));
+    tokio::spawn(keep_access_token(
+        input.state,
+        access_token.expire_time,
+        state.request.clone(),
+    ));
+
     let app = Router::new()
         .route("/update", post(update))
         .route("/status", get(get_status))
+        .route("/lyrics", get(get_lyrics).post(convert_lyrics))
         .route("/auto_update", post(auto_update))
+        .route("/feishu/events", post(feishu_events))
         .with_state(state);
 
     axum::serve(listener, app).await.unwrap();
     Ok(())
 }
 
+fn run_command(
+    command: Command,
+    config_path: &Path,
+    key: Option<&SecretKey>,
+) -> std::result::Result<(), String> {
+    if let Command::Convert {
+        input,
+        from,
+        to,
+        output,
+    } = command
+    {
+        let text = std::fs::read_to_string(&input)
+            .map_err(|error| format!("Unable to read {}: {}", input.display(), error))?;
+        let lyrics = Import::parse(&text, from)
+            .map_err(|error| format!("{}: {}", input.display(), error))?;
+        let options = ExportOptions {
+            ass: Config::load(config_path, key)
+                .map(|config| config.lyrics.ass)
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let data = Export::render(&lyrics, to, &options)?;
+        return match output {
+            Some(name) => ResponseHandler::create_file(&data, &name, to),
+            None => {
+                println!("{}", data);
+                Ok(())
+            }
+        };
+    }
+    let key = match key {
+        Some(key) => key,
+        None => Err("No key set, use --key-file, SIREN_KEY_FILE or SIREN_PASSPHRASE".to_string())?,
+    };
+    let new_key = match command {
+        Command::Encrypt | Command::Convert { .. } => key.clone(),
+        Command::Rotate { new_key_file } => match new_key_file {
+            Some(path) => SecretKey::from_file(&path)?,
+            None => match std::env::var("SIREN_NEW_PASSPHRASE") {
+                Ok(passphrase) if !passphrase.is_empty() => SecretKey::from_passphrase(&passphrase),
+                _ => Err("No new key set, use --new-key-file or SIREN_NEW_PASSPHRASE".to_string())?,
+            },
+        },
+    };
+    let fields = Config::reseal_file(config_path, key, &new_key)?;
+    if fields.is_empty() {
+        println!("No secrets found in {}", config_path.display());
+    } else {
+        println!(
+            "Encrypted {} in {}",
+            fields.join(", "),
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Refresh apple music access token in the background before it expires
+async fn keep_access_token(
+    state_path: PathBuf,
+    mut expire_time: u128,
+    request: Arc<Mutex<Request>>,
+) {
+    loop {
+        let now = chrono::Utc::now().timestamp_millis() as u128;
+        let refresh_time = Token::refresh_time(expire_time);
+        if refresh_time > now {
+            let wait = Duration::from_millis((refresh_time - now) as u64);
+            tokio::time::sleep(wait.min(ACCESS_TOKEN_CHECK_INTERVAL)).await;
+            continue;
+        }
+
+        match Token::refresh_access_token(&state_path).await {
+            Ok(access_token) => {
+                expire_time = access_token.expire_time;
+                request.lock().await.set_authorization(access_token.token);
+                println!("Access token refreshed");
+            }
+            Err(error) => eprintln!("Unable to refresh access token: {}", error),
+        }
+        tokio::time::sleep(ACCESS_TOKEN_RETRY_INTERVAL).await;
+    }
+}
+
+/// Keep the feishu status title following playback progress or lyrics
+async fn live_status(state: ShareState) {
+    // Lyrics of the last song, fetched once per track
+    let mut lyrics: Option<(String, Vec<TimedLine>)> = None;
+    let mut last_lyric: Option<String> = None;
+
+    loop {
+        let (mode, interval) = {
+            let feishu_request = state.feishu_request.lock().await;
+            (feishu_request.live(), feishu_request.live_interval())
+        };
+        tokio::time::sleep(interval).await;
+        let mode = match mode {
+            Some(mode) => mode,
+            None => continue,
+        };
+
+        let now_listening = state.now_listening.lock().await.clone();
+        let start_time = match now_listening.start_time {
+            Some(start_time) if now_listening.is_playing => start_time,
+            _ => {
+                last_lyric = None;
+                continue;
+            }
+        };
+        let now = chrono::Local::now().timestamp_millis() as u128;
+        let elapsed = now.saturating_sub(start_time) as u64;
+
+        let lyric = match (mode, &now_listening.song_id) {
+            (feishu::LiveMode::Lyrics, Some(song_id)) => {
+                if lyrics.as_ref().map(|(id, _)| id) != Some(song_id) {
+                    let lines = match load_lyrics(&state, song_id, true, None).await {
+                        Ok(lyrics) => ResponseHandler::timed_lines(&lyrics),
+                        Err(error) => {
+                            eprintln!("Unable to get lyrics for live status: {}", error);
+                            Vec::new()
+                        }
+                    };
+                    lyrics = Some((song_id.clone(), lines));
+                }
+                lyrics
+                    .as_ref()
+                    .and_then(|(_, lines)| TimedLine::at(lines, elapsed))
+                    .map(|line| line.to_string())
+            }
+            _ => None,
+        };
+        // The same lyric line needs no update, progress changes every time
+        if lyric.is_some() && lyric == last_lyric {
+            continue;
+        }
+        last_lyric = lyric.clone();
+
+        let mut feishu_request = state.feishu_request.lock().await;
+        if let Err(error) = feishu_request
+            .update_live(&now_listening, lyric.as_deref())
+            .await
+        {
+            eprintln!("Unable to update live status: {}", error);
+        }
+    }
+}
+
+/// Reload config when the file changes or on SIGHUP
+async fn watch_config(path: PathBuf, key: Option<SecretKey>, state: ShareState) {
+    let mut sighup = signal(SignalKind::hangup()).expect("Unable to listen for SIGHUP");
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    let mut last_modified = config_modified(&path);
+
+    loop {
+        tokio::select! {
+            _ = sighup.recv() => {
+                println!("SIGHUP received, reloading config...");
+            }
+            _ = interval.tick() => {
+                let modified = config_modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                println!("Config file changed, reloading config...");
+            }
+        }
+        reload_config(&path, key.as_ref(), &state).await;
+    }
+}
+
+fn config_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Apply a new config to the running state, or keep the old one if it is invalid
+async fn reload_config(path: &Path, key: Option<&SecretKey>, state: &ShareState) {
+    let mut new_config = match Config::load(path, key) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}\nKeeping the last good config", error);
+            return;
+        }
+    };
+
+    let mut request = state.request.lock().await;
+    let mut feishu_request = state.feishu_request.lock().await;
+    let mut config = state.config.lock().await;
+
+    let changes = config.diff(&new_config);
+    if changes.is_empty() {
+        println!("Config reloaded: no changes");
+        return;
+    }
+    if new_config.user_token.is_none() {
+        new_config.user_token = config.user_token.clone();
+    }
+    if let Some(user_token) = &new_config.user_token {
+        request.set_user_token(user_token.clone());
+    }
+    feishu_request.apply_config(&new_config);
+    feishu_request.resolve_users().await;
+    *config = new_config;
+    println!("Config reloaded:");
+    for change in changes {
+        println!("  {}", change);
+    }
+}
+
 async fn update(
     State(state): State<Arc<Mutex<NowListening>>>,
     Json(payload): Json<NowListening>,
@@ -137,6 +515,9 @@ async fn update(
     now_listening.artist = payload.artist;
     now_listening.album = payload.album;
     now_listening.album_cover = payload.album_cover;
+    now_listening.url = payload.url;
+    now_listening.song_id = payload.song_id;
+    now_listening.isrc = payload.isrc;
     now_listening.start_time = payload.start_time;
     println!("Updated: {:?}", now_listening);
     Ok("Updated".to_string())
@@ -154,6 +535,11 @@ async fn auto_update(
 
     println!("Auto update: {:?}", payload);
 
+    let mut stop_task = state.stop_task.lock().await;
+    if let Some(task) = stop_task.take() {
+        task.abort();
+    }
+
     match payload.play_status {
         PlayStatus::Stopped => {
             now_listening.is_playing = false;
@@ -162,20 +548,31 @@ async fn auto_update(
             now_listening.artist = None;
             now_listening.album = None;
             now_listening.album_cover = None;
+            now_listening.url = None;
+            now_listening.song_id = None;
+            now_listening.isrc = None;
             now_listening.start_time = None;
-
-            feishu_request.refresh_token().await;
-            feishu_request.update_status(now_listening.clone()).await;
-            let now = chrono::Local::now();
-            let time = now.timestamp_millis() as u128 + 114514;
-            feishu_request.set_status(time).await;
+            now_listening.credits = None;
+
+            // Wait before closing so short gaps between tracks don't flicker
+            let grace_period = Duration::from_secs(feishu_request.stop_grace_period());
+            let feishu_request = state.feishu_request.clone();
+            *stop_task = Some(tokio::spawn(async move {
+                tokio::time::sleep(grace_period).await;
+                let mut feishu_request = feishu_request.lock().await;
+                match feishu_request.close_status().await {
+                    Ok(failures) => report_failures(&failures),
+                    Err(error) => eprintln!("Unable to close feishu status: {}", error),
+                }
+            }));
             return Ok("Not playing".to_string());
         }
         PlayStatus::Playing => {
-            let url = request.create_search_url(
-                payload.name.clone().unwrap().as_str(),
-                payload.artist.clone().unwrap().as_str(),
-            );
+            let (name, artist) = match (&payload.name, &payload.artist) {
+                (Some(name), Some(artist)) => (name.clone(), artist.clone()),
+                _ => Err(anyhow::anyhow!("Playing without a track name or artist"))?,
+            };
+            let url = request.create_search_url(&name, &artist);
 
             let res = match client.get(url).send().await {
                 Ok(r) => {
@@ -195,9 +592,11 @@ async fn auto_update(
                 }
             };
 
+            let new_track = now_listening.name.as_ref() != Some(&name)
+                || now_listening.artist != Some(vec![artist.clone()]);
             now_listening.is_playing = true;
-            now_listening.name = Some(payload.name.unwrap());
-            now_listening.artist = Some(vec![payload.artist.unwrap()]);
+            now_listening.name = Some(name);
+            now_listening.artist = Some(vec![artist]);
             now_listening.album = res_json["results"]["top"]["data"][0]["attributes"]["albumName"]
                 .as_str()
                 .map(|s| s.to_string());
@@ -205,24 +604,50 @@ async fn auto_update(
                 ["artwork"]["url"]
                 .as_str()
                 .map(|s| s.to_string());
+            now_listening.url = res_json["results"]["top"]["data"][0]["attributes"]["url"]
+                .as_str()
+                .map(|s| s.to_string());
+            now_listening.song_id = res_json["results"]["top"]["data"][0]["id"]
+                .as_str()
+                .map(|s| s.to_string());
+            now_listening.isrc = res_json["results"]["top"]["data"][0]["attributes"]["isrc"]
+                .as_str()
+                .map(|s| s.to_string());
             now_listening.duration =
                 res_json["results"]["top"]["data"][0]["attributes"]["durationInMillis"].as_u64();
             now_listening.start_time = Some(chrono::Local::now().timestamp_millis() as u128);
+            if new_track {
+                now_listening.credits = None;
+                if let Some(song_id) = now_listening.song_id.clone() {
+                    tokio::spawn(fetch_credits(state.clone(), song_id));
+                }
+            }
 
-            feishu_request.refresh_token().await;
-            feishu_request.update_status(now_listening.clone()).await;
+            feishu_request.update_status(now_listening.clone()).await?;
             let now = chrono::Local::now();
-            let time = now.timestamp_millis() as u128 + now_listening.duration.unwrap() as u128;
-            feishu_request.set_status(time).await;
+            let time = now.timestamp_millis() as u128
+                + now_listening
+                    .duration
+                    .map(|duration| duration as u128)
+                    .unwrap_or(DEFAULT_STATUS_DURATION);
+            report_failures(&feishu_request.set_status(time).await?);
+            if new_track {
+                if let Err(error) = feishu_request.announce(&now_listening).await {
+                    eprintln!("Unable to send feishu card: {}", error);
+                }
+            }
         }
         PlayStatus::Paused => {
             now_listening.is_playing = false;
 
-            feishu_request.refresh_token().await;
-            feishu_request.update_status(now_listening.clone()).await;
+            feishu_request.update_status(now_listening.clone()).await?;
             let now = chrono::Local::now();
-            let time = now.timestamp_millis() as u128 + now_listening.duration.unwrap() as u128;
-            feishu_request.set_status(time).await;
+            let time = now.timestamp_millis() as u128
+                + now_listening
+                    .duration
+                    .map(|duration| duration as u128)
+                    .unwrap_or(DEFAULT_STATUS_DURATION);
+            report_failures(&feishu_request.set_status(time).await?);
 
             return Ok("Paused".to_string());
         }
@@ -232,12 +657,286 @@ async fn auto_update(
     Ok("Updated".to_string())
 }
 
+/// Add the songwriters of a new track to the status, once the lyrics are fetched
+async fn fetch_credits(state: ShareState, song_id: String) {
+    let credits = match load_lyrics(&state, &song_id, true, None).await {
+        Ok(lyrics) => lyrics.credits,
+        Err(error) => {
+            eprintln!("Unable to get credits for {}: {}", song_id, error);
+            return;
+        }
+    };
+    let mut now_listening = state.now_listening.lock().await;
+    if now_listening.song_id.as_ref() == Some(&song_id) {
+        now_listening.credits = Some(credits);
+    }
+}
+
+/// Feishu event callback, answers url verification and runs `/siren` commands
+async fn feishu_events(
+    State(state): State<ShareState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let events = match &state.config.lock().await.events {
+        Some(events) => events.clone(),
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let now = chrono::Utc::now().timestamp();
+    let event = match feishu::Event::parse(&events, &headers, &body, now) {
+        Ok(event) => event,
+        Err(error) => {
+            // The same answer for every failure, so decrypt errors can't be told apart
+            eprintln!("Invalid feishu event: {}", error);
+            return (StatusCode::BAD_REQUEST, "Invalid event").into_response();
+        }
+    };
+    match event {
+        feishu::Event::Challenge(challenge) => {
+            Json(serde_json::json!({ "challenge": challenge })).into_response()
+        }
+        feishu::Event::Message {
+            event_id,
+            message_id,
+            text,
+        } => {
+            if !state.seen_events.lock().await.insert(&event_id) {
+                return StatusCode::OK.into_response();
+            }
+            // Feishu expects an answer within a few seconds, so reply in the background
+            if let Some(command) = feishu::ChatCommand::parse(&text) {
+                tokio::spawn(run_chat_command(state, events.tenant, message_id, command));
+            }
+            StatusCode::OK.into_response()
+        }
+        feishu::Event::Ignored => StatusCode::OK.into_response(),
+    }
+}
+
+async fn run_chat_command(
+    state: ShareState,
+    tenant: Option<String>,
+    message_id: String,
+    command: feishu::ChatCommand,
+) {
+    println!("Chat command: {:?}", command);
+    let now_listening = state.now_listening.lock().await.clone();
+    let reply = match command {
+        feishu::ChatCommand::Now => match &now_listening.name {
+            Some(name) => format!(
+                "{} {} - {}{}",
+                if now_listening.is_playing {
+                    "🎵"
+                } else {
+                    "⏸️"
+                },
+                name,
+                now_listening.artist.clone().unwrap_or_default().join(", "),
+                now_listening
+                    .url
+                    .as_ref()
+                    .map(|url| format!("\n{}", url))
+                    .unwrap_or_default()
+            ),
+            None => "Nothing is playing".to_string(),
+        },
+        feishu::ChatCommand::Lyrics => match &now_listening.song_id {
+            Some(song_id) => {
+                match load_lyrics(&state, song_id, true, None)
+                    .await
+                    .map(|lyrics| ResponseHandler::plain_lines(&lyrics))
+                {
+                    Ok(lines) if !lines.is_empty() => lines.join("\n"),
+                    Ok(_) => "No lyrics found".to_string(),
+                    Err(error) => format!("Unable to get lyrics: {}", error),
+                }
+            }
+            None => "Nothing is playing".to_string(),
+        },
+        feishu::ChatCommand::Pause => {
+            let mut feishu_request = state.feishu_request.lock().await;
+            feishu_request.set_sharing(false);
+            match feishu_request.close_status().await {
+                Ok(failures) => {
+                    report_failures(&failures);
+                    "Sharing paused".to_string()
+                }
+                Err(error) => format!("Sharing paused, unable to close status: {}", error),
+            }
+        }
+        feishu::ChatCommand::Resume => {
+            let mut feishu_request = state.feishu_request.lock().await;
+            feishu_request.set_sharing(true);
+            match now_listening.duration {
+                Some(duration) if now_listening.name.is_some() => {
+                    let time = chrono::Local::now().timestamp_millis() as u128 + duration as u128;
+                    let result = match feishu_request.update_status(now_listening).await {
+                        Ok(()) => feishu_request.set_status(time).await,
+                        Err(error) => Err(error),
+                    };
+                    match result {
+                        Ok(failures) => {
+                            report_failures(&failures);
+                            "Sharing resumed".to_string()
+                        }
+                        Err(error) => {
+                            format!("Sharing resumed, unable to update status: {}", error)
+                        }
+                    }
+                }
+                _ => "Sharing resumed".to_string(),
+            }
+        }
+        feishu::ChatCommand::Help => feishu::ChatCommand::usage().to_string(),
+    };
+
+    let mut feishu_request = state.feishu_request.lock().await;
+    if let Err(error) = feishu_request
+        .reply(tenant.as_deref(), &message_id, &reply)
+        .await
+    {
+        eprintln!("Unable to reply to feishu: {}", error);
+    }
+}
+
+fn report_failures(failures: &[feishu::UserFailure]) {
+    for failure in failures {
+        eprintln!(
+            "[{}] Feishu status failed for {}: {}",
+            failure.tenant, failure.user_id, failure.reason
+        );
+    }
+}
+
 async fn get_status(State(state): State<Arc<Mutex<NowListening>>>) -> Result<Json<NowListening>> {
     let now_listening = state.lock().await;
     println!("Get status: {:?}", now_listening);
     Ok(Json(now_listening.clone()))
 }
 
+async fn get_lyrics(
+    State(state): State<ShareState>,
+    Query(query): Query<LyricsQuery>,
+) -> Result<Response> {
+    let song_id = match &query.song {
+        Some(song) if song.contains("://") => Request::get_song_id(song),
+        Some(song) => song.clone(),
+        None => state
+            .now_listening
+            .lock()
+            .await
+            .song_id
+            .clone()
+            .unwrap_or_default(),
+    };
+    if song_id.is_empty() {
+        return Ok((StatusCode::NOT_FOUND, "No song to get lyrics for").into_response());
+    }
+
+    // Word timed exports need the spaces between words
+    let space = query.space || query.format != LyricsFormat::Json;
+    let lyrics = load_lyrics(&state, &song_id, space, query.lang.as_deref())
+        .await
+        .map_err(|error| anyhow::anyhow!(error))?;
+    let ass = state.config.lock().await.lyrics.ass.clone();
+    Ok(export_lyrics(&lyrics, &query, ass, &song_id))
+}
+
+/// Lyrics from the local library, otherwise from Apple Music and saved to the library
+/// when enabled, library files are served as they are
+async fn load_lyrics(
+    state: &ShareState,
+    song_id: &str,
+    space: bool,
+    lang: Option<&str>,
+) -> std::result::Result<LyricsJSON, String> {
+    let keys = {
+        let now_listening = state.now_listening.lock().await;
+        song_keys(&now_listening, song_id)
+    };
+    let library = state.config.lock().await.lyrics.library.clone();
+    if let Some(library) = &library {
+        match Library::load(library, &keys, space, lang) {
+            Ok(Some(lyrics)) => return Ok(lyrics),
+            Ok(None) => (),
+            Err(error) => eprintln!(
+                "Unable to load lyrics from the library, using Apple Music: {}",
+                error
+            ),
+        }
+    }
+
+    let text = state.request.lock().await.get_lyrics(song_id).await?;
+    let lyrics = ResponseHandler::extract_lyrics_to_json(&text, space, lang)?;
+    if let Some(library) = library.filter(|library| library.save) {
+        match Library::save(&library, song_id, &text) {
+            Ok(path) => println!("Saved lyrics to {}", path.display()),
+            Err(error) => eprintln!("Unable to save lyrics of {}: {}", song_id, error),
+        }
+    }
+    Ok(lyrics)
+}
+
+/// Library names of a song, with the ISRC, artist and title when it is the current track
+fn song_keys(now_listening: &NowListening, song_id: &str) -> SongKeys {
+    let mut keys = SongKeys {
+        song_id: Some(song_id.to_string()),
+        ..Default::default()
+    };
+    if now_listening.song_id.as_deref() == Some(song_id) {
+        keys.isrc = now_listening.isrc.clone();
+        keys.artist = now_listening
+            .artist
+            .as_ref()
+            .map(|artist| artist.join(", "));
+        keys.title = now_listening.name.clone();
+    }
+    keys
+}
+
+/// Convert lyrics in the request body, like an lrc or TTML file, to `format`
+async fn convert_lyrics(
+    State(state): State<ShareState>,
+    Query(query): Query<LyricsQuery>,
+    body: String,
+) -> Result<Response> {
+    let lyrics = match Import::parse(&body, query.from) {
+        Ok(lyrics) => lyrics,
+        Err(error) => {
+            return Ok((StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response())
+        }
+    };
+    let ass = state.config.lock().await.lyrics.ass.clone();
+    Ok(export_lyrics(&lyrics, &query, ass, "lyrics"))
+}
+
+/// Lyrics written in the format of the query, or why they can't be
+fn export_lyrics(lyrics: &LyricsJSON, query: &LyricsQuery, ass: AssConfig, name: &str) -> Response {
+    let options = ExportOptions {
+        time_format: query.time_format,
+        lrc: LrcOptions {
+            variant: query.lrc,
+            offset: query.offset,
+            translation: query.translation,
+        },
+        background_settings: query.background_settings.clone(),
+        ass,
+    };
+    let file_name = format!("inline; filename=\"{}.{}\"", name, query.format.extension());
+    match Export::render(lyrics, query.format, &options) {
+        Ok(body) => (
+            [
+                (LYRICS_SYNC_HEADER, lyrics.sync.name()),
+                (header::CONTENT_TYPE.as_str(), query.format.content_type()),
+                (header::CONTENT_DISPOSITION.as_str(), &file_name),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(error) => (StatusCode::UNPROCESSABLE_ENTITY, error).into_response(),
+    }
+}
+
 #[derive(Debug)]
 pub struct Error(anyhow::Error);
 impl IntoResponse for Error {
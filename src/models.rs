@@ -2,4 +2,5 @@ pub mod apple_music;
 pub mod lyric_json;
 pub mod lyric_xml;
 pub mod synced_lyric_xml;
+pub mod timestamp;
 pub mod user_storefront;
@@ -7,17 +7,33 @@ pub struct AppleMusic {
 
 #[derive(Serialize, Deserialize)]
 pub struct AppleMusicData {
+    #[serde(default)]
+    pub attributes: SongAttributes,
     pub relationships: Relationships,
 }
 
+/// Catalog metadata of the song
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SongAttributes {
+    pub name: Option<String>,
+    pub artist_name: Option<String>,
+    pub album_name: Option<String>,
+    pub duration_in_millis: Option<u64>,
+}
+
+/// Either kind of lyrics may be missing, depending on the track
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Relationships {
+    #[serde(default)]
     pub syllable_lyrics: Lyrics,
+    #[serde(default)]
     pub lyrics: Lyrics,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Lyrics {
     pub href: String,
     pub data: Vec<LyricsDatum>,
@@ -1,40 +1,180 @@
+use crate::models::timestamp::{TimeFormat, Timestamp};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How precisely the lyrics are timed, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncLevel {
+    /// Every word is timed, for karaoke views
+    Syllable,
+    /// Every line is timed
+    Line,
+    /// Plain text
+    Unsynced,
+}
+
+impl SyncLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncLevel::Syllable => "syllable",
+            SyncLevel::Line => "line",
+            SyncLevel::Unsynced => "unsynced",
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct LyricsJSON {
-    lines: Vec<Line>,
+    pub sync: SyncLevel,
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Singers of duets, referenced by `Line::singer`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub singers: Vec<Singer>,
+    /// Languages of the available translations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<String>,
+    /// Language of the translation in the lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Credits::is_empty")]
+    pub credits: Credits,
+    pub lines: Vec<Line>,
+}
+
+/// People credited in the lyrics
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Credits {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub songwriters: Vec<String>,
+}
+
+impl Credits {
+    pub fn is_empty(&self) -> bool {
+        self.songwriters.is_empty()
+    }
+}
+
+/// Person or group singing some of the lines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Singer {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: SingerKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SingerKind {
+    Person,
+    /// Everyone together
+    Group,
+    #[serde(other)]
+    Other,
+}
+
+/// Song the lyrics belong to, from the catalog
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Timestamp>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Line {
-    begin: String,
-    end: String,
-    words: Vec<Word>,
-    background: Vec<Word>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub begin: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<Timestamp>,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub singer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+    /// The line in latin script, for lyrics in other scripts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub romanization: Option<String>,
+    pub words: Vec<Word>,
+    pub background: Vec<Word>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Word {
-    begin: String,
-    end: String,
-    text: String,
+    pub begin: Timestamp,
+    pub end: Timestamp,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub romanization: Option<String>,
 }
 
 impl LyricsJSON {
-    pub fn new() -> Self {
-        Self { lines: Vec::new() }
+    pub fn new(sync: SyncLevel) -> Self {
+        Self {
+            sync,
+            metadata: Metadata::default(),
+            singers: Vec::new(),
+            translations: Vec::new(),
+            language: None,
+            credits: Credits::default(),
+            lines: Vec::new(),
+        }
     }
 
     pub fn add_line(&mut self, line: Line) {
         self.lines.push(line);
     }
+
+    pub fn singer(&self, id: &str) -> Option<&Singer> {
+        self.singers.iter().find(|singer| singer.id == id)
+    }
+
+    /// Json with timestamps written in `time_format`
+    pub fn to_value(&self, time_format: TimeFormat) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if time_format == TimeFormat::Legacy {
+            Self::stringify_times(&mut value);
+        }
+        value
+    }
+
+    fn stringify_times(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    match value.as_u64() {
+                        Some(millis) if key == "begin" || key == "end" => {
+                            *value = Value::String(Timestamp::from_millis(millis).to_string())
+                        }
+                        _ => Self::stringify_times(value),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(Self::stringify_times),
+            _ => (),
+        }
+    }
 }
 
 impl Line {
-    pub fn new(begin: String, end: String) -> Self {
+    pub fn new(begin: Option<Timestamp>, end: Option<Timestamp>, text: String) -> Self {
         Self {
             begin,
             end,
+            text,
+            singer: None,
+            translation: None,
+            romanization: None,
             words: Vec::new(),
             background: Vec::new(),
         }
@@ -50,7 +190,12 @@ impl Line {
 }
 
 impl Word {
-    pub fn new(begin: String, end: String, text: String) -> Self {
-        Self { begin, end, text }
+    pub fn new(begin: Timestamp, end: Timestamp, text: String) -> Self {
+        Self {
+            begin,
+            end,
+            text,
+            romanization: None,
+        }
     }
 }
@@ -1,11 +1,113 @@
+use crate::models::timestamp::Timestamp;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename = "tt")]
 pub struct LyricXML {
+    #[serde(default)]
+    pub head: Head,
     pub body: Body,
 }
 
+/// Namespace prefixes are dropped, `ttm:agent` is read as `agent`
+#[derive(Default, Serialize, Deserialize)]
+pub struct Head {
+    #[serde(default)]
+    pub metadata: HeadMetadata,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct HeadMetadata {
+    #[serde(default)]
+    pub agent: Vec<Agent>,
+    #[serde(rename = "iTunesMetadata", default)]
+    pub itunes: ITunesMetadata,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ITunesMetadata {
+    #[serde(default)]
+    pub translations: Translations,
+    #[serde(default)]
+    pub transliterations: Transliterations,
+    #[serde(default)]
+    pub songwriters: Songwriters,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Songwriters {
+    #[serde(default)]
+    pub songwriter: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Translations {
+    #[serde(default)]
+    pub translation: Vec<Translation>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Transliterations {
+    #[serde(default)]
+    pub transliteration: Vec<Translation>,
+}
+
+/// Lines in one language, `xml:lang` is read as `lang`
+#[derive(Serialize, Deserialize)]
+pub struct Translation {
+    #[serde(rename = "@lang")]
+    pub lang: Option<String>,
+    #[serde(default)]
+    pub text: Vec<TranslatedText>,
+}
+
+/// A line, matched to the body by its `itunes:key`
+#[derive(Serialize, Deserialize)]
+pub struct TranslatedText {
+    #[serde(rename = "@for")]
+    pub key: String,
+    #[serde(rename = "$value", default)]
+    pub content: Vec<TextContent>,
+}
+
+/// Plain text, or timed spans in word level transliterations
+#[derive(Serialize, Deserialize)]
+pub enum TextContent {
+    #[serde(rename = "$text")]
+    Text(String),
+    #[serde(rename = "span")]
+    Span(TextSpan),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TextSpan {
+    #[serde(rename = "@begin")]
+    pub begin: Option<Timestamp>,
+    #[serde(rename = "@end")]
+    pub end: Option<Timestamp>,
+    #[serde(rename = "$value", default)]
+    pub content: Vec<TextContent>,
+}
+
+/// A singer, `v1`, `v2`, ... for persons and `v1000` for the group
+#[derive(Serialize, Deserialize)]
+pub struct Agent {
+    #[serde(rename = "@id")]
+    pub id: String,
+    #[serde(rename = "@type")]
+    pub agent_type: Option<String>,
+    #[serde(default)]
+    pub name: Vec<AgentName>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AgentName {
+    #[serde(rename = "@type")]
+    pub name_type: Option<String>,
+    #[serde(rename = "$text", default)]
+    pub name: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Body {
     pub div: Vec<Div>,
@@ -16,12 +118,18 @@ pub struct Div {
     pub p: Vec<P>,
 }
 
+/// Times are missing when the lyrics aren't synced
 #[derive(Serialize, Deserialize)]
 pub struct P {
     #[serde(rename = "@begin")]
-    pub begin: String,
+    pub begin: Option<Timestamp>,
     #[serde(rename = "@end")]
-    pub end: String,
-    #[serde(rename = "$text")]
+    pub end: Option<Timestamp>,
+    #[serde(rename = "@agent")]
+    pub agent: Option<String>,
+    /// `itunes:key` of the line, like `L1`
+    #[serde(rename = "@key")]
+    pub key: Option<String>,
+    #[serde(rename = "$text", default)]
     pub line: String,
 }
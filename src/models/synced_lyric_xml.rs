@@ -1,8 +1,12 @@
+use crate::models::lyric_xml::Head;
+use crate::models::timestamp::Timestamp;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename = "tt")]
 pub struct SynedLyricXML {
+    #[serde(default)]
+    pub head: Head,
     pub body: Body,
 }
 
@@ -19,18 +23,24 @@ pub struct Div {
 #[derive(Serialize, Deserialize)]
 pub struct P {
     #[serde(rename = "@begin")]
-    pub begin: String,
+    pub begin: Timestamp,
     #[serde(rename = "@end")]
-    pub end: String,
+    pub end: Timestamp,
+    /// Id of the singer declared in the head
+    #[serde(rename = "@agent")]
+    pub agent: Option<String>,
+    /// `itunes:key` of the line, like `L1`
+    #[serde(rename = "@key")]
+    pub key: Option<String>,
     pub span: Vec<Span>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Span {
     #[serde(rename = "@begin")]
-    pub begin: Option<String>,
+    pub begin: Option<Timestamp>,
     #[serde(rename = "@end")]
-    pub end: Option<String>,
+    pub end: Option<Timestamp>,
     #[serde(rename = "$text")]
     pub word: Option<String>,
     pub span: Option<Vec<Span>>,
@@ -0,0 +1,232 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// TTML defaults when the document doesn't set `ttp:frameRate` or `ttp:tickRate`
+const DEFAULT_FRAME_RATE: f64 = 30.0;
+const DEFAULT_TICK_RATE: f64 = 1.0;
+
+/// A TTML time expression with millisecond precision
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+/// How timestamps are written in json output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeFormat {
+    /// Numeric milliseconds
+    #[default]
+    Millis,
+    /// Strings like `1:02.345`, as sent by Apple Music
+    Legacy,
+}
+
+impl Timestamp {
+    pub fn from_millis(millis: u64) -> Self {
+        Self(millis)
+    }
+
+    pub fn as_millis(self) -> u64 {
+        self.0
+    }
+
+    /// Parse clock time like `1:02:03.456`, `02:03.4`, `01:02:03:15` (frames)
+    /// or offset time like `12.5`, `12.5s`, `1500ms`, `2m`, `1h`, `45f`, `10t`
+    pub fn parse(time: &str) -> Result<Self, String> {
+        let time = time.trim();
+        let seconds = if time.contains(':') {
+            Self::parse_clock_time(time)
+        } else {
+            Self::parse_offset_time(time)
+        };
+        match seconds {
+            Some(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+                Ok(Self((seconds * 1000.0).round() as u64))
+            }
+            _ => Err(format!("invalid time \"{}\"", time)),
+        }
+    }
+
+    fn parse_clock_time(time: &str) -> Option<f64> {
+        let parts: Vec<&str> = time.split(':').collect();
+        let (hours, minutes, seconds, frames) = match parts.as_slice() {
+            [minutes, seconds] => ("0", *minutes, *seconds, None),
+            [hours, minutes, seconds] => (*hours, *minutes, *seconds, None),
+            [hours, minutes, seconds, frames] => (*hours, *minutes, *seconds, Some(*frames)),
+            _ => return None,
+        };
+        let hours = Self::parse_number(hours, false)?;
+        let minutes = Self::parse_number(minutes, false)?;
+        let seconds = Self::parse_number(seconds, frames.is_none())?;
+        let frames = match frames {
+            Some(frames) => Self::parse_number(frames, true)? / DEFAULT_FRAME_RATE,
+            None => 0.0,
+        };
+        Some(hours * 3600.0 + minutes * 60.0 + seconds + frames)
+    }
+
+    fn parse_offset_time(time: &str) -> Option<f64> {
+        let split = time
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(time.len());
+        let (count, metric) = time.split_at(split);
+        let count = Self::parse_number(count, true)?;
+        match metric {
+            "" | "s" => Some(count),
+            "ms" => Some(count / 1000.0),
+            "m" => Some(count * 60.0),
+            "h" => Some(count * 3600.0),
+            "f" => Some(count / DEFAULT_FRAME_RATE),
+            "t" => Some(count / DEFAULT_TICK_RATE),
+            _ => None,
+        }
+    }
+
+    /// Digits, with an optional fraction when `fraction` is set
+    fn parse_number(number: &str, fraction: bool) -> Option<f64> {
+        let (whole, part) = match number.split_once('.') {
+            Some((whole, part)) if fraction => (whole, part),
+            Some(_) => return None,
+            None => (number, ""),
+        };
+        let digits = |text: &str| text.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(part) {
+            return None;
+        }
+        number.parse().ok()
+    }
+
+    /// `mm:ss.xx` as used in lrc files
+    pub fn to_lrc(self) -> String {
+        let centis = self.0 / 10;
+        format!(
+            "{:02}:{:02}.{:02}",
+            centis / 6000,
+            centis / 100 % 60,
+            centis % 100
+        )
+    }
+
+    /// `hh:mm:ss,mmm` as used in srt files
+    pub fn to_srt(self) -> String {
+        format!(
+            "{:02}:{:02}:{:02},{:03}",
+            self.0 / 3600000,
+            self.0 / 60000 % 60,
+            self.0 / 1000 % 60,
+            self.0 % 1000
+        )
+    }
+
+    /// Rounded centiseconds, the unit of ass times and `\k` durations
+    pub fn as_centis(self) -> u64 {
+        (self.0 + 5) / 10
+    }
+
+    /// `h:mm:ss.cc` as used in ass files
+    pub fn to_ass(self) -> String {
+        let centis = self.as_centis();
+        format!(
+            "{}:{:02}:{:02}.{:02}",
+            centis / 360000,
+            centis / 6000 % 60,
+            centis / 100 % 60,
+            centis % 100
+        )
+    }
+
+    /// `mm:ss.mmm`, or `hh:mm:ss.mmm` past an hour, as used in WebVTT files
+    pub fn to_vtt(self) -> String {
+        let minutes = format!(
+            "{:02}:{:02}.{:03}",
+            self.0 / 60000 % 60,
+            self.0 / 1000 % 60,
+            self.0 % 1000
+        );
+        match self.0 / 3600000 {
+            0 => minutes,
+            hours => format!("{:02}:{}", hours, minutes),
+        }
+    }
+}
+
+/// Apple Music style, `h:mm:ss.fff`, `m:ss.fff` or `s.fff`
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0 % 1000;
+        let seconds = self.0 / 1000 % 60;
+        let minutes = self.0 / 60000 % 60;
+        let hours = self.0 / 3600000;
+        if hours > 0 {
+            write!(f, "{}:{:02}:{:02}.{:03}", hours, minutes, seconds, millis)
+        } else if minutes > 0 {
+            write!(f, "{}:{:02}.{:03}", minutes, seconds, millis)
+        } else {
+            write!(f, "{}.{:03}", seconds, millis)
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+/// TTML strings, or milliseconds from our own json output
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Millis(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Millis(millis) => Ok(Self(millis)),
+            Raw::Text(time) => Self::parse(&time).map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::timestamp::Timestamp;
+
+    #[test]
+    fn parse_clock_time() {
+        let millis = |time: &str| Timestamp::parse(time).map(|time| time.as_millis());
+        assert_eq!(Ok(3723456), millis("1:02:03.456"));
+        assert_eq!(Ok(123400), millis("02:03.4"));
+        assert_eq!(Ok(123457), millis("2:03.4567"));
+        assert_eq!(Ok(3723500), millis("01:02:03:15"));
+        assert!(millis("1:2:3:4:5").is_err());
+        assert!(millis("1.5:03").is_err());
+        assert!(millis("").is_err());
+    }
+
+    #[test]
+    fn parse_offset_time() {
+        let millis = |time: &str| Timestamp::parse(time).map(|time| time.as_millis());
+        assert_eq!(Ok(12500), millis("12.5"));
+        assert_eq!(Ok(12500), millis("12.5s"));
+        assert_eq!(Ok(1500), millis("1500ms"));
+        assert_eq!(Ok(90000), millis("1.5m"));
+        assert_eq!(Ok(3600000), millis("1h"));
+        assert_eq!(Ok(1500), millis("45f"));
+        assert_eq!(Ok(10000), millis("10t"));
+        assert!(millis("12x").is_err());
+        assert!(millis("-1s").is_err());
+    }
+
+    #[test]
+    fn format_timestamp() {
+        assert_eq!("3.050", Timestamp::from_millis(3050).to_string());
+        assert_eq!("1:02.345", Timestamp::from_millis(62345).to_string());
+        assert_eq!("1:02:03.456", Timestamp::from_millis(3723456).to_string());
+        assert_eq!("62:03.45", Timestamp::from_millis(3723456).to_lrc());
+        assert_eq!("01:02:03,456", Timestamp::from_millis(3723456).to_srt());
+        assert_eq!("01:02:03.456", Timestamp::from_millis(3723456).to_vtt());
+        assert_eq!("01:02.345", Timestamp::from_millis(62345).to_vtt());
+        assert_eq!("1:02:03.46", Timestamp::from_millis(3723456).to_ass());
+    }
+}
@@ -1,3 +1,9 @@
 pub mod apple_music_url;
+pub mod export_handler;
+pub mod import_handler;
+pub mod library_handler;
 pub mod response_handler;
+pub mod secret_handler;
+pub mod state_handler;
+pub mod text_handler;
 pub mod token_handler;
@@ -1,14 +1,25 @@
 use crate::models::user_storefront::UserStorefront;
 use reqwest::header::HeaderMap;
 use reqwest::{header, Client};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Request {
     authorization: String,
     user_token: String,
     storefront: String,
 }
 
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("authorization", &"***")
+            .field("user_token", &"***")
+            .field("storefront", &self.storefront)
+            .finish()
+    }
+}
+
 impl Request {
     pub(crate) fn new(authorization: String, user_token: String) -> Self {
         Self {
@@ -18,6 +29,14 @@ impl Request {
         }
     }
 
+    pub(crate) fn set_authorization(&mut self, authorization: String) {
+        self.authorization = authorization;
+    }
+
+    pub(crate) fn set_user_token(&mut self, user_token: String) {
+        self.user_token = user_token;
+    }
+
     pub(crate) fn create_header(&mut self) -> HeaderMap {
         let mut headers = header::HeaderMap::new();
         headers.insert("origin", "https://music.apple.com".parse().unwrap());
@@ -45,6 +64,24 @@ impl Request {
         }
     }
 
+    /// Raw catalog response with the lyrics relationships of a song
+    pub(crate) async fn get_lyrics(&mut self, song_id: &str) -> Result<String, String> {
+        let headers = self.create_header();
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|error| error.to_string())?;
+        let res = client
+            .get(self.create_lyrics_url(song_id))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        if !res.status().is_success() {
+            Err(format!("Invalid response: {}", res.status()))?
+        }
+        res.text().await.map_err(|error| error.to_string())
+    }
+
     pub(crate) fn get_song_id(url: &str) -> String {
         let mut match_header: i8 = 0;
         let mut id: String = String::new();
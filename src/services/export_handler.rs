@@ -0,0 +1,786 @@
+use crate::models::lyric_json::{Line, LyricsJSON, SingerKind, SyncLevel, Word};
+use crate::models::timestamp::{TimeFormat, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Written to the `[by:]` tag of exported files
+const CREATOR: &str = "Siren";
+/// Canvas of ass files, font sizes and margins are relative to it
+const ASS_WIDTH: u32 = 1920;
+const ASS_HEIGHT: u32 = 1080;
+const ASS_STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+const ASS_EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// Output format of exported lyrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsFormat {
+    #[default]
+    Json,
+    Lrc,
+    /// SubRip subtitles
+    Srt,
+    /// WebVTT subtitles with word timestamps
+    Vtt,
+    /// Advanced SubStation Alpha karaoke
+    Ass,
+}
+
+impl LyricsFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LyricsFormat::Json => "json",
+            LyricsFormat::Lrc => "lrc",
+            LyricsFormat::Srt => "srt",
+            LyricsFormat::Vtt => "vtt",
+            LyricsFormat::Ass => "ass",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            LyricsFormat::Json => "application/json",
+            LyricsFormat::Lrc => "text/plain; charset=utf-8",
+            LyricsFormat::Srt => "application/x-subrip; charset=utf-8",
+            LyricsFormat::Vtt => "text/vtt; charset=utf-8",
+            LyricsFormat::Ass => "text/x-ssa; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for LyricsFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "json" => Ok(LyricsFormat::Json),
+            "lrc" => Ok(LyricsFormat::Lrc),
+            "srt" => Ok(LyricsFormat::Srt),
+            "vtt" => Ok(LyricsFormat::Vtt),
+            "ass" => Ok(LyricsFormat::Ass),
+            _ => Err(format!("unknown lyrics format \"{}\"", format)),
+        }
+    }
+}
+
+/// Flavour of lrc output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LrcVariant {
+    /// One timestamp per line, understood by every player
+    Simple,
+    /// A2 word timestamps for the main vocals
+    #[default]
+    Enhanced,
+    /// A2 word timestamps with background vocals on a line of their own
+    Background,
+}
+
+/// Styles of ass karaoke subtitles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssConfig {
+    /// `\kf` sweeps through each syllable, `\k` highlights it at once
+    pub sweep: bool,
+    pub font: String,
+    pub font_size: u32,
+    /// Colour of sung syllables, `#RRGGBB` or `&HAABBGGRR`
+    pub primary_colour: String,
+    /// Colour of syllables before they are sung
+    pub secondary_colour: String,
+    pub outline_colour: String,
+    /// Numpad position, 2 is bottom centre
+    pub alignment: u8,
+    /// Sung colour of background vocals, which are smaller and in italics
+    pub background_colour: String,
+    pub background_alignment: u8,
+}
+
+impl Default for AssConfig {
+    fn default() -> Self {
+        Self {
+            sweep: true,
+            font: "Arial".to_string(),
+            font_size: 64,
+            primary_colour: "#FFFFFF".to_string(),
+            secondary_colour: "#808080".to_string(),
+            outline_colour: "#000000".to_string(),
+            alignment: 2,
+            background_colour: "#C0C0C0".to_string(),
+            background_alignment: 8,
+        }
+    }
+}
+
+impl AssConfig {
+    /// Report invalid colours and alignments, prefixed with the config path
+    pub(crate) fn validate(&self, prefix: &str, errors: &mut Vec<String>) {
+        let colours = [
+            ("primary_colour", &self.primary_colour),
+            ("secondary_colour", &self.secondary_colour),
+            ("outline_colour", &self.outline_colour),
+            ("background_colour", &self.background_colour),
+        ];
+        for (name, colour) in colours {
+            if Self::colour(colour).is_none() {
+                errors.push(format!("{}{} \"{}\" is invalid", prefix, name, colour));
+            }
+        }
+        for (name, alignment) in [
+            ("alignment", self.alignment),
+            ("background_alignment", self.background_alignment),
+        ] {
+            if !(1..=9).contains(&alignment) {
+                errors.push(format!("{}{} must be between 1 and 9", prefix, name));
+            }
+        }
+        if self.font.trim().is_empty() || self.font.contains(',') {
+            errors.push(format!("{}font \"{}\" is invalid", prefix, self.font));
+        }
+    }
+
+    /// `&HAABBGGRR` from `#RRGGBB`, `&HBBGGRR` or `&HAABBGGRR`
+    fn colour(colour: &str) -> Option<String> {
+        let colour = colour.trim();
+        let hex = |text: &str| text.chars().all(|c| c.is_ascii_hexdigit());
+        if let Some(rgb) = colour.strip_prefix('#') {
+            if rgb.len() != 6 || !hex(rgb) {
+                return None;
+            }
+            let (red, green, blue) = (&rgb[0..2], &rgb[2..4], &rgb[4..6]);
+            return Some(format!("&H00{}{}{}", blue, green, red).to_uppercase());
+        }
+        let bgr = colour
+            .strip_prefix("&H")
+            .or_else(|| colour.strip_prefix("&h"))?;
+        match bgr.len() {
+            6 if hex(bgr) => Some(format!("&H00{}", bgr.to_uppercase())),
+            8 if hex(bgr) => Some(format!("&H{}", bgr.to_uppercase())),
+            _ => None,
+        }
+    }
+
+    /// A style line, colours are checked by `validate` when the config is loaded
+    fn style(&self, name: &str, primary: &str, size: u32, italic: bool, alignment: u8) -> String {
+        let colour = |colour: &str| Self::colour(colour).unwrap_or_else(|| "&H00FFFFFF".into());
+        format!(
+            "Style: {},{},{},{},{},{},&H80000000,0,{},0,0,100,100,0,0,1,3,0,{},60,60,60,1",
+            name,
+            self.font.trim(),
+            size,
+            colour(primary),
+            colour(&self.secondary_colour),
+            colour(&self.outline_colour),
+            if italic { -1 } else { 0 },
+            alignment
+        )
+    }
+}
+
+/// How lrc output is written
+#[derive(Debug, Clone, Default)]
+pub struct LrcOptions {
+    pub variant: LrcVariant,
+    /// Milliseconds for the `[offset:]` tag
+    pub offset: Option<i64>,
+    /// Follow every line with its translation, at the same time
+    pub translation: bool,
+}
+
+/// Everything the export formats can be tuned with
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub time_format: TimeFormat,
+    pub lrc: LrcOptions,
+    /// WebVTT cue settings for background vocals
+    pub background_settings: Option<String>,
+    pub ass: AssConfig,
+}
+
+pub struct Export {}
+
+/// Subtitle cue of a line, or of the background vocals of a line
+struct Cue<'a> {
+    begin: Timestamp,
+    end: Timestamp,
+    text: String,
+    /// Word timings, empty for line synced lyrics
+    words: &'a [Word],
+    background: bool,
+    /// Name of the singer, or their id when the name is unknown
+    singer: Option<&'a str>,
+    side: Side,
+}
+
+/// Where the lines of a singer go in duets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+    Center,
+}
+
+impl Export {
+    /// Lyrics written in `format`
+    pub(crate) fn render(
+        lyrics: &LyricsJSON,
+        format: LyricsFormat,
+        options: &ExportOptions,
+    ) -> Result<String, String> {
+        match format {
+            LyricsFormat::Json => Ok(lyrics.to_value(options.time_format).to_string()),
+            LyricsFormat::Lrc => Ok(Self::lrc(lyrics, &options.lrc)),
+            LyricsFormat::Srt => Self::srt(lyrics),
+            LyricsFormat::Vtt => Self::vtt(lyrics, options.background_settings.as_deref()),
+            LyricsFormat::Ass => Self::ass(lyrics, &options.ass),
+        }
+    }
+
+    /// Lrc with header tags, line synced lyrics are always written as simple lrc
+    pub(crate) fn lrc(lyrics: &LyricsJSON, options: &LrcOptions) -> String {
+        let mut lrc = Self::lrc_header(lyrics, options.offset);
+        let sides = Self::sides(lyrics);
+        let variant = options.variant;
+        for line in &lyrics.lines {
+            let translation = match &line.translation {
+                Some(translation) if options.translation && !translation.is_empty() => {
+                    Some(translation)
+                }
+                _ => None,
+            };
+            let begin = match line.begin {
+                Some(begin) if lyrics.sync != SyncLevel::Unsynced => begin.to_lrc(),
+                _ => {
+                    lrc.push_str(&format!("{}\n", line.text));
+                    if let Some(translation) = translation {
+                        lrc.push_str(&format!("{}\n", translation));
+                    }
+                    continue;
+                }
+            };
+            if lyrics.sync != SyncLevel::Syllable
+                || variant == LrcVariant::Simple
+                || line.words.is_empty()
+            {
+                lrc.push_str(&format!("[{}]{}\n", begin, line.text.trim()));
+                if let Some(translation) = translation {
+                    lrc.push_str(&format!("[{}]{}\n", begin, translation));
+                }
+                continue;
+            }
+            // Duet parts are marked like `v1:`, as some karaoke players do
+            let voice = match line.singer.as_deref() {
+                Some(singer) if sides.contains_key(singer) => format!("{}:", singer),
+                _ => String::new(),
+            };
+            lrc.push_str(&format!(
+                "[{}]{}{}\n",
+                begin,
+                voice,
+                Self::a2_words(&line.words)
+            ));
+            if variant == LrcVariant::Background {
+                if let Some(first) = line.background.first() {
+                    lrc.push_str(&format!(
+                        "[{}]{}{}\n",
+                        first.begin.to_lrc(),
+                        voice,
+                        Self::a2_words(&line.background)
+                    ));
+                }
+            }
+            if let Some(translation) = translation {
+                lrc.push_str(&format!("[{}]{}\n", begin, translation));
+            }
+        }
+        lrc
+    }
+
+    /// SubRip cues, background vocals get cues of their own
+    pub(crate) fn srt(lyrics: &LyricsJSON) -> Result<String, String> {
+        let cues = Self::cues(lyrics)?;
+        Ok(cues
+            .iter()
+            .enumerate()
+            .map(|(index, cue)| {
+                format!(
+                    "{}\n{} --> {}\n{}\n\n",
+                    index + 1,
+                    cue.begin.to_srt(),
+                    cue.end.to_srt(),
+                    cue.text
+                )
+            })
+            .collect())
+    }
+
+    /// WebVTT cues with inline word timestamps for karaoke,
+    /// `background_settings` like `line:0 align:end` are added to background vocal cues
+    pub(crate) fn vtt(
+        lyrics: &LyricsJSON,
+        background_settings: Option<&str>,
+    ) -> Result<String, String> {
+        let background_settings = match background_settings.map(str::trim) {
+            Some(settings) if settings.contains('\n') || settings.contains("-->") => {
+                Err(format!("invalid cue settings \"{}\"", settings))?
+            }
+            Some(settings) if !settings.is_empty() => format!(" {}", settings),
+            _ => String::new(),
+        };
+        let mut vtt = String::from("WEBVTT\n\n");
+        if !lyrics.credits.songwriters.is_empty() {
+            vtt.push_str(&format!(
+                "NOTE Written by {}\n\n",
+                lyrics.credits.songwriters.join(", ").replace("-->", "->")
+            ));
+        }
+        for cue in Self::cues(lyrics)? {
+            let mut settings = match cue.side {
+                Side::Left => " align:start".to_string(),
+                Side::Right => " align:end".to_string(),
+                Side::Center => String::new(),
+            };
+            if cue.background {
+                settings.push_str(&background_settings);
+            }
+            let mut text = match cue.words.is_empty() {
+                true => Self::escape_vtt(&cue.text),
+                false => {
+                    let mut text = String::new();
+                    for word in cue.words {
+                        // Timestamps have to be inside the cue
+                        if word.begin > cue.begin && word.begin < cue.end {
+                            text.push_str(&format!("<{}>", word.begin.to_vtt()));
+                        }
+                        text.push_str(&Self::escape_vtt(&word.text));
+                    }
+                    text.trim_end().to_string()
+                }
+            };
+            if let Some(singer) = cue.singer {
+                text = format!("<v {}>{}</v>", Self::escape_vtt(singer), text);
+            }
+            vtt.push_str(&format!(
+                "{} --> {}{}\n{}\n\n",
+                cue.begin.to_vtt(),
+                cue.end.to_vtt(),
+                settings,
+                text
+            ));
+        }
+        Ok(vtt)
+    }
+
+    /// Advanced SubStation Alpha karaoke, a dialogue per line with `\k` syllable durations
+    pub(crate) fn ass(lyrics: &LyricsJSON, config: &AssConfig) -> Result<String, String> {
+        let cues = Self::cues(lyrics)?;
+        let mut ass = String::from("[Script Info]\n");
+        if let Some(title) = &lyrics.metadata.title {
+            ass.push_str(&format!("Title: {}\n", Self::escape_ass(title)));
+        }
+        ass.push_str(&format!(
+            "ScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 0\nScaledBorderAndShadow: yes\n\n",
+            ASS_WIDTH, ASS_HEIGHT
+        ));
+        ass.push_str(&format!("[V4+ Styles]\nFormat: {}\n", ASS_STYLE_FORMAT));
+        ass.push_str(&config.style(
+            "Main",
+            &config.primary_colour,
+            config.font_size,
+            false,
+            config.alignment,
+        ));
+        ass.push('\n');
+        // Duet parts at the left and right end of the same row
+        let row = (config.alignment.clamp(1, 9) - 1) / 3 * 3;
+        for (name, alignment) in [("Left", row + 1), ("Right", row + 3)] {
+            ass.push_str(&config.style(
+                name,
+                &config.primary_colour,
+                config.font_size,
+                false,
+                alignment,
+            ));
+            ass.push('\n');
+        }
+        ass.push_str(&config.style(
+            "Background",
+            &config.background_colour,
+            config.font_size * 3 / 4,
+            true,
+            config.background_alignment,
+        ));
+        ass.push_str(&format!("\n\n[Events]\nFormat: {}\n", ASS_EVENT_FORMAT));
+
+        let tag = if config.sweep { "kf" } else { "k" };
+        for cue in cues {
+            let (layer, style) = match (cue.background, cue.side) {
+                (true, _) => (1, "Background"),
+                (false, Side::Left) => (0, "Left"),
+                (false, Side::Right) => (0, "Right"),
+                (false, Side::Center) => (0, "Main"),
+            };
+            let name = cue.singer.unwrap_or_default().replace(',', " ");
+            let text = match cue.words.is_empty() {
+                true => Self::escape_ass(&cue.text),
+                false => Self::karaoke(cue.begin, cue.words, tag),
+            };
+            ass.push_str(&format!(
+                "Dialogue: {},{},{},{},{},0,0,0,,{}\n",
+                layer,
+                cue.begin.to_ass(),
+                cue.end.to_ass(),
+                style,
+                name,
+                text
+            ));
+        }
+        Ok(ass)
+    }
+
+    /// `{\k}` tags for each word, with empty ones for silences before and between words
+    fn karaoke(begin: Timestamp, words: &[Word], tag: &str) -> String {
+        let mut text = String::new();
+        let mut cursor = begin.as_centis();
+        for word in words {
+            let start = word.begin.as_centis().max(cursor);
+            if start > cursor {
+                text.push_str(&format!("{{\\k{}}}", start - cursor));
+            }
+            let end = word.end.as_centis().max(start);
+            text.push_str(&format!(
+                "{{\\{}{}}}{}",
+                tag,
+                end - start,
+                Self::escape_ass(&word.text)
+            ));
+            cursor = end;
+        }
+        text.trim_end().to_string()
+    }
+
+    /// Braces start override tags and line breaks end the event
+    fn escape_ass(text: &str) -> String {
+        text.replace('{', "(")
+            .replace('}', ")")
+            .replace("\r\n", " ")
+            .replace(['\r', '\n'], " ")
+    }
+
+    /// Cues in order of their start, skipping empty lines
+    fn cues(lyrics: &LyricsJSON) -> Result<Vec<Cue<'_>>, String> {
+        if lyrics.sync == SyncLevel::Unsynced {
+            Err("lyrics are not synced".to_string())?
+        }
+        let sides = Self::sides(lyrics);
+        let mut cues = Vec::new();
+        for line in &lyrics.lines {
+            let (Some(begin), Some(end)) = (line.begin, line.end) else {
+                continue;
+            };
+            let singer = line.singer.as_deref().map(|id| {
+                lyrics
+                    .singer(id)
+                    .and_then(|singer| singer.name.as_deref())
+                    .unwrap_or(id)
+            });
+            let side = Self::side(&sides, line);
+            if !line.text.trim().is_empty() {
+                cues.push(Cue {
+                    begin,
+                    end,
+                    text: line.text.trim().to_string(),
+                    words: &line.words,
+                    background: false,
+                    singer,
+                    side,
+                });
+            }
+            if let (Some(first), Some(last)) = (line.background.first(), line.background.last()) {
+                cues.push(Cue {
+                    begin: first.begin,
+                    end: last.end,
+                    text: line
+                        .background
+                        .iter()
+                        .map(|word| word.text.as_str())
+                        .collect::<String>()
+                        .trim()
+                        .to_string(),
+                    words: &line.background,
+                    background: true,
+                    singer,
+                    side,
+                });
+            }
+        }
+        cues.sort_by_key(|cue| cue.begin);
+        Ok(cues)
+    }
+
+    /// Sides of the singers, empty unless at least two of them take turns.
+    /// Singers alternate left and right in order of appearance, the group is centred
+    fn sides(lyrics: &LyricsJSON) -> HashMap<&str, Side> {
+        let mut sides = HashMap::new();
+        let mut voices = 0;
+        for singer in lyrics
+            .lines
+            .iter()
+            .filter_map(|line| line.singer.as_deref())
+        {
+            if sides.contains_key(singer) {
+                continue;
+            }
+            let group = lyrics
+                .singer(singer)
+                .is_some_and(|singer| singer.kind == SingerKind::Group);
+            let side = match (group, voices % 2) {
+                (true, _) => Side::Center,
+                (false, 0) => Side::Left,
+                (false, _) => Side::Right,
+            };
+            if !group {
+                voices += 1;
+            }
+            sides.insert(singer, side);
+        }
+        if voices < 2 {
+            sides.clear();
+        }
+        sides
+    }
+
+    fn side(sides: &HashMap<&str, Side>, line: &Line) -> Side {
+        line.singer
+            .as_deref()
+            .and_then(|singer| sides.get(singer))
+            .copied()
+            .unwrap_or(Side::Center)
+    }
+
+    fn escape_vtt(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    /// `[ti:]`, `[ar:]`, `[al:]`, `[au:]`, `[length:]`, `[by:]` and `[offset:]` for the known values
+    fn lrc_header(lyrics: &LyricsJSON, offset: Option<i64>) -> String {
+        let metadata = &lyrics.metadata;
+        let mut tags: Vec<(&str, String)> = Vec::new();
+        if let Some(title) = &metadata.title {
+            tags.push(("ti", title.clone()));
+        }
+        if let Some(artist) = &metadata.artist {
+            tags.push(("ar", artist.clone()));
+        }
+        if let Some(album) = &metadata.album {
+            tags.push(("al", album.clone()));
+        }
+        if !lyrics.credits.songwriters.is_empty() {
+            tags.push(("au", lyrics.credits.songwriters.join(", ")));
+        }
+        if let Some(duration) = metadata.duration {
+            let seconds = duration.as_millis() / 1000;
+            tags.push(("length", format!("{:02}:{:02}", seconds / 60, seconds % 60)));
+        }
+        tags.push(("by", CREATOR.to_string()));
+        if let Some(offset) = offset {
+            tags.push(("offset", format!("{:+}", offset)));
+        }
+        tags.iter()
+            .map(|(tag, value)| format!("[{}:{}]\n", tag, value))
+            .collect()
+    }
+
+    /// `<mm:ss.xx>word` for every word, closed with the end time of the last one
+    fn a2_words(words: &[Word]) -> String {
+        let mut text: String = words
+            .iter()
+            .map(|word| format!("<{}>{}", word.begin.to_lrc(), word.text))
+            .collect();
+        if let Some(last) = words.last() {
+            text.truncate(text.trim_end().len());
+            text.push_str(&format!("<{}>", last.end.to_lrc()));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::lyric_json::{
+        Line, LyricsJSON, Metadata, Singer, SingerKind, SyncLevel, Word,
+    };
+    use crate::models::timestamp::Timestamp;
+    use crate::services::export_handler::{AssConfig, Export, LrcOptions, LrcVariant};
+
+    fn syllable_lyrics() -> LyricsJSON {
+        let time = Timestamp::from_millis;
+        let word =
+            |begin: u64, end: u64, text: &str| Word::new(time(begin), time(end), text.to_string());
+        let mut lyrics = LyricsJSON::new(SyncLevel::Syllable);
+        lyrics.metadata = Metadata {
+            title: Some("Song".to_string()),
+            artist: Some("Artist".to_string()),
+            album: None,
+            duration: Some(time(185000)),
+        };
+        let mut line = Line::new(
+            Some(time(1500)),
+            Some(time(4000)),
+            "Hello world".to_string(),
+        );
+        line.add_words(word(1500, 2000, "Hello "));
+        line.add_words(word(2000, 3000, "world"));
+        line.add_background(word(3000, 4000, "(ooh)"));
+        lyrics.add_line(line);
+        lyrics
+    }
+
+    fn lrc_options(variant: LrcVariant, offset: Option<i64>) -> LrcOptions {
+        LrcOptions {
+            variant,
+            offset,
+            translation: false,
+        }
+    }
+
+    #[test]
+    fn lrc_variants() {
+        let lyrics = syllable_lyrics();
+        let header = "[ti:Song]\n[ar:Artist]\n[length:03:05]\n[by:Siren]\n";
+        assert_eq!(
+            format!("{}[00:01.50]Hello world\n", header),
+            Export::lrc(&lyrics, &lrc_options(LrcVariant::Simple, None))
+        );
+        assert_eq!(
+            format!(
+                "{}[offset:-250]\n[00:01.50]<00:01.50>Hello <00:02.00>world<00:03.00>\n",
+                header
+            ),
+            Export::lrc(&lyrics, &lrc_options(LrcVariant::Enhanced, Some(-250)))
+        );
+        assert_eq!(
+            format!(
+                "{}[00:01.50]<00:01.50>Hello <00:02.00>world<00:03.00>\n[00:03.00]<00:03.00>(ooh)<00:04.00>\n",
+                header
+            ),
+            Export::lrc(&lyrics, &lrc_options(LrcVariant::Background, None))
+        );
+
+        let mut lyrics = syllable_lyrics();
+        lyrics.sync = SyncLevel::Unsynced;
+        lyrics.metadata = Metadata::default();
+        assert_eq!(
+            "[by:Siren]\nHello world\n",
+            Export::lrc(&lyrics, &lrc_options(LrcVariant::Enhanced, None))
+        );
+
+        let mut lyrics = syllable_lyrics();
+        lyrics.metadata = Metadata::default();
+        lyrics.lines[0].translation = Some("Hallo Welt".to_string());
+        lyrics.credits.songwriters = vec!["Ann".to_string(), "Bob".to_string()];
+        let options = LrcOptions {
+            translation: true,
+            ..lrc_options(LrcVariant::Simple, None)
+        };
+        assert_eq!(
+            "[au:Ann, Bob]\n[by:Siren]\n[00:01.50]Hello world\n[00:01.50]Hallo Welt\n",
+            Export::lrc(&lyrics, &options)
+        );
+    }
+
+    #[test]
+    fn subtitles() {
+        let lyrics = syllable_lyrics();
+        assert_eq!(
+            "1\n00:00:01,500 --> 00:00:04,000\nHello world\n\n2\n00:00:03,000 --> 00:00:04,000\n(ooh)\n\n",
+            Export::srt(&lyrics).unwrap()
+        );
+        assert_eq!(
+            "WEBVTT\n\n00:01.500 --> 00:04.000\nHello <00:02.000>world\n\n00:03.000 --> 00:04.000 line:0\n(ooh)\n\n",
+            Export::vtt(&lyrics, Some("line:0")).unwrap()
+        );
+        assert!(Export::vtt(&lyrics, Some("line:0\n-->")).is_err());
+
+        let mut lyrics = syllable_lyrics();
+        lyrics.sync = SyncLevel::Unsynced;
+        assert!(Export::srt(&lyrics).is_err());
+    }
+
+    #[test]
+    fn ass_karaoke() {
+        let mut lyrics = syllable_lyrics();
+        lyrics.lines[0].begin = Some(Timestamp::from_millis(1000));
+        lyrics.lines[0].words[1].begin = Timestamp::from_millis(2250);
+        let ass = Export::ass(&lyrics, &AssConfig::default()).unwrap();
+        assert!(ass.contains("Title: Song\n"));
+        let mut titled = syllable_lyrics();
+        titled.metadata.title = Some("Song\r\n[Events]{\\k1}".to_string());
+        let ass = Export::ass(&titled, &AssConfig::default()).unwrap();
+        assert!(ass.contains("Title: Song [Events](\\k1)\n"));
+        let ass = Export::ass(&lyrics, &AssConfig::default()).unwrap();
+        assert!(ass.contains("Style: Main,Arial,64,&H00FFFFFF,&H00808080,&H00000000,"));
+        assert!(ass.contains("Style: Background,Arial,48,&H00C0C0C0,"));
+        assert!(ass.contains(
+            "Dialogue: 0,0:00:01.00,0:00:04.00,Main,,0,0,0,,{\\k50}{\\kf50}Hello {\\k25}{\\kf75}world\n"
+        ));
+        assert!(
+            ass.contains("Dialogue: 1,0:00:03.00,0:00:04.00,Background,,0,0,0,,{\\kf100}(ooh)\n")
+        );
+
+        let config = AssConfig {
+            primary_colour: "#12345".to_string(),
+            alignment: 0,
+            ..AssConfig::default()
+        };
+        let mut errors = Vec::new();
+        config.validate("lyrics.ass.", &mut errors);
+        assert_eq!(2, errors.len());
+        assert_eq!(
+            Some("&H80FF0000".to_string()),
+            AssConfig::colour("&h80ff0000")
+        );
+        assert_eq!(Some("&H000000FF".to_string()), AssConfig::colour("#FF0000"));
+    }
+
+    #[test]
+    fn duet_sides() {
+        let time = Timestamp::from_millis;
+        let mut lyrics = LyricsJSON::new(SyncLevel::Line);
+        lyrics.singers = vec![
+            Singer {
+                id: "v1".to_string(),
+                name: Some("Ann".to_string()),
+                kind: SingerKind::Person,
+            },
+            Singer {
+                id: "v1000".to_string(),
+                name: None,
+                kind: SingerKind::Group,
+            },
+        ];
+        for (index, singer) in ["v1", "v2", "v1000"].iter().enumerate() {
+            let begin = index as u64 * 1000;
+            let mut line = Line::new(Some(time(begin)), Some(time(begin + 1000)), "La".into());
+            line.singer = Some(singer.to_string());
+            lyrics.add_line(line);
+        }
+
+        let vtt = Export::vtt(&lyrics, None).unwrap();
+        assert!(vtt.contains("00:00.000 --> 00:01.000 align:start\n<v Ann>La</v>\n"));
+        assert!(vtt.contains("00:01.000 --> 00:02.000 align:end\n<v v2>La</v>\n"));
+        assert!(vtt.contains("00:02.000 --> 00:03.000\n<v v1000>La</v>\n"));
+        let ass = Export::ass(&lyrics, &AssConfig::default()).unwrap();
+        assert!(ass.contains("Style: Left,Arial,64,&H00FFFFFF,&H00808080,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,0,1,"));
+        assert!(ass.contains("Dialogue: 0,0:00:00.00,0:00:01.00,Left,Ann,0,0,0,,La\n"));
+        assert!(ass.contains("Dialogue: 0,0:00:01.00,0:00:02.00,Right,v2,0,0,0,,La\n"));
+        assert!(ass.contains("Dialogue: 0,0:00:02.00,0:00:03.00,Main,v1000,0,0,0,,La\n"));
+
+        lyrics.lines.truncate(1);
+        assert!(Export::sides(&lyrics).is_empty());
+    }
+}
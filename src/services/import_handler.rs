@@ -0,0 +1,668 @@
+use crate::models::lyric_json::{Line, LyricsJSON, Singer, SingerKind, SyncLevel, Word};
+use crate::models::lyric_xml::LyricXML;
+use crate::models::synced_lyric_xml::SynedLyricXML;
+use crate::models::timestamp::Timestamp;
+use crate::services::response_handler::Response;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Format of a lyrics file to import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Standard or enhanced lrc
+    Lrc,
+    Srt,
+    Vtt,
+    Ttml,
+    /// Json as served by the lyrics endpoint
+    Json,
+}
+
+impl ImportFormat {
+    /// Guess the format from how the file starts
+    pub fn detect(text: &str) -> Self {
+        let text = text.trim_start_matches('\u{feff}').trim_start();
+        if text.starts_with("WEBVTT") {
+            ImportFormat::Vtt
+        } else if text.starts_with('<') {
+            ImportFormat::Ttml
+        } else if text.starts_with('{') {
+            ImportFormat::Json
+        } else if text.lines().take(3).any(|line| line.contains("-->")) {
+            ImportFormat::Srt
+        } else {
+            ImportFormat::Lrc
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "lrc" => Ok(ImportFormat::Lrc),
+            "srt" => Ok(ImportFormat::Srt),
+            "vtt" => Ok(ImportFormat::Vtt),
+            "ttml" | "xml" => Ok(ImportFormat::Ttml),
+            "json" => Ok(ImportFormat::Json),
+            _ => Err(format!("unknown lyrics format \"{}\"", format)),
+        }
+    }
+}
+
+/// Where a lyrics file can't be read, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Error at a byte offset of `text`
+    fn at_offset(text: &str, offset: usize, message: impl Into<String>) -> Self {
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+        Self::new(line, column, message)
+    }
+}
+
+/// Text of a subtitle cue or lrc line, split at word timestamps
+struct Marks {
+    /// Start of each word and the text after it
+    marks: Vec<(Timestamp, String)>,
+    /// Text before the first timestamp
+    leading: String,
+}
+
+impl Marks {
+    fn new() -> Self {
+        Self {
+            marks: Vec::new(),
+            leading: String::new(),
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        match self.marks.last_mut() {
+            Some((_, word)) => word.push_str(text),
+            None => self.leading.push_str(text),
+        }
+    }
+
+    fn push_time(&mut self, time: Timestamp) {
+        self.marks.push((time, String::new()));
+    }
+
+    fn text(&self) -> String {
+        let words: String = self.marks.iter().map(|(_, text)| text.as_str()).collect();
+        format!("{}{}", self.leading, words).trim().to_string()
+    }
+
+    /// Words end where the next one starts, a trailing timestamp closes the last one
+    fn words(&self, end: Option<Timestamp>) -> Vec<Word> {
+        let mut words = Vec::new();
+        for (index, (begin, text)) in self.marks.iter().enumerate() {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let word_end = self
+                .marks
+                .get(index + 1)
+                .map(|(next, _)| *next)
+                .or(end)
+                .unwrap_or(*begin);
+            words.push(Word::new(*begin, word_end.max(*begin), text.clone()));
+        }
+        words
+    }
+}
+
+pub struct Import {}
+
+impl Import {
+    /// Read lyrics in `format`, or in the format detected from the text
+    pub(crate) fn parse(
+        text: &str,
+        format: Option<ImportFormat>,
+    ) -> Result<LyricsJSON, ParseError> {
+        let text = text.trim_start_matches('\u{feff}');
+        match format.unwrap_or_else(|| ImportFormat::detect(text)) {
+            ImportFormat::Lrc => Self::lrc(text),
+            ImportFormat::Srt => Self::srt(text),
+            ImportFormat::Vtt => Self::vtt(text),
+            ImportFormat::Ttml => Self::ttml(text),
+            ImportFormat::Json => serde_json::from_str(text)
+                .map_err(|error| ParseError::new(error.line(), error.column(), error.to_string())),
+        }
+    }
+
+    /// Standard and enhanced lrc, header tags fill the metadata and `[offset:]` is applied
+    fn lrc(text: &str) -> Result<LyricsJSON, ParseError> {
+        let mut lyrics = LyricsJSON::new(SyncLevel::Unsynced);
+        let mut offset: i64 = 0;
+        let mut timed: Vec<Line> = Vec::new();
+        let mut plain: Vec<Line> = Vec::new();
+
+        for (index, raw) in text.lines().enumerate() {
+            let number = index + 1;
+            let raw = raw.trim_end();
+            let start = raw.len() - raw.trim_start().len();
+            let mut position = start;
+            let mut times: Vec<Timestamp> = Vec::new();
+            while raw[position..].starts_with('[') {
+                let close = raw[position..].find(']').ok_or_else(|| {
+                    ParseError::new(number, Self::column(raw, position), "unclosed tag")
+                })?;
+                let tag = &raw[position + 1..position + close];
+                let error = |message: String| {
+                    ParseError::new(number, Self::column(raw, position + 1), message)
+                };
+                if tag.starts_with(|c: char| c.is_ascii_digit()) {
+                    times.push(Timestamp::parse(tag).map_err(error)?);
+                } else if let Some((key, value)) = tag.split_once(':') {
+                    Self::lrc_tag(&mut lyrics, &mut offset, key.trim(), value.trim())
+                        .map_err(error)?;
+                }
+                // Section markers like `[Chorus]` are skipped
+                position += close + 1;
+            }
+
+            if times.is_empty() {
+                if position == start && !raw.trim().is_empty() {
+                    plain.push(Line::new(None, None, raw.trim().to_string()));
+                }
+                continue;
+            }
+            let (singer, marks) = Self::lrc_marks(raw, position, number)?;
+            for begin in &times {
+                let mut line = Line::new(Some(*begin), None, marks.text());
+                line.singer = singer.clone();
+                // Repeated lines like `[00:12.00][01:30.00]` only have word times for one of them
+                if times.len() == 1 {
+                    line.words = marks.words(None);
+                }
+                timed.push(line);
+            }
+        }
+
+        if timed.is_empty() {
+            lyrics.lines = plain;
+            return Ok(lyrics);
+        }
+        let shift = |time: Timestamp| {
+            Timestamp::from_millis((time.as_millis() as i64 - offset).max(0) as u64)
+        };
+        for line in &mut timed {
+            line.begin = line.begin.map(shift);
+            for word in &mut line.words {
+                word.begin = shift(word.begin);
+                word.end = shift(word.end);
+            }
+        }
+        timed.sort_by_key(|line| line.begin);
+        let starts: Vec<Option<Timestamp>> = timed.iter().map(|line| line.begin).collect();
+        for (index, line) in timed.iter_mut().enumerate() {
+            let begin = line.begin.unwrap_or_default();
+            let closed = line
+                .words
+                .last()
+                .map(|word| word.end)
+                .filter(|end| *end > begin);
+            let next = starts
+                .get(index + 1)
+                .copied()
+                .flatten()
+                .filter(|next| *next > begin);
+            let duration = lyrics
+                .metadata
+                .duration
+                .filter(|duration| *duration > begin);
+            line.end = closed.or(next).or(duration);
+            if let (Some(end), Some(last)) = (line.end, line.words.last_mut()) {
+                if last.end == last.begin {
+                    last.end = end;
+                }
+            }
+        }
+        lyrics.sync = match timed.iter().any(|line| !line.words.is_empty()) {
+            true => SyncLevel::Syllable,
+            false => SyncLevel::Line,
+        };
+        lyrics.lines = timed;
+        Ok(lyrics)
+    }
+
+    /// `[ti:]`, `[ar:]`, `[al:]`, `[au:]`, `[length:]` and `[offset:]`, others are ignored
+    fn lrc_tag(
+        lyrics: &mut LyricsJSON,
+        offset: &mut i64,
+        key: &str,
+        value: &str,
+    ) -> Result<(), String> {
+        let value_of = || Some(value.to_string()).filter(|value| !value.is_empty());
+        match key.to_lowercase().as_str() {
+            "ti" => lyrics.metadata.title = value_of(),
+            "ar" => lyrics.metadata.artist = value_of(),
+            "al" => lyrics.metadata.album = value_of(),
+            "au" => {
+                lyrics.credits.songwriters = value
+                    .split(',')
+                    .map(|songwriter| songwriter.trim().to_string())
+                    .filter(|songwriter| !songwriter.is_empty())
+                    .collect()
+            }
+            "length" if !value.is_empty() => {
+                lyrics.metadata.duration = Some(Timestamp::parse(value)?);
+            }
+            "offset" if !value.is_empty() => {
+                *offset = value
+                    .parse()
+                    .map_err(|_| format!("invalid offset \"{}\"", value))?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Text of an lrc line from `position`, with `<mm:ss.xx>` word times and a `v1:` duet marker
+    fn lrc_marks(
+        raw: &str,
+        position: usize,
+        number: usize,
+    ) -> Result<(Option<String>, Marks), ParseError> {
+        let mut position = position;
+        let mut singer = None;
+        if let Some((voice, rest)) = raw[position..].split_once(':') {
+            let is_voice = voice.len() > 1
+                && voice.starts_with('v')
+                && voice[1..].chars().all(|c| c.is_ascii_digit());
+            if is_voice && rest.starts_with('<') {
+                singer = Some(voice.to_string());
+                position += voice.len() + 1;
+            }
+        }
+
+        let mut marks = Marks::new();
+        while position < raw.len() {
+            let rest = &raw[position..];
+            let Some(open) = rest.find('<') else {
+                marks.push_text(rest);
+                break;
+            };
+            marks.push_text(&rest[..open]);
+            let close = rest[open..].find('>').ok_or_else(|| {
+                ParseError::new(
+                    number,
+                    Self::column(raw, position + open),
+                    "unclosed word time",
+                )
+            })?;
+            let time = Timestamp::parse(&rest[open + 1..open + close]).map_err(|error| {
+                ParseError::new(number, Self::column(raw, position + open + 1), error)
+            })?;
+            marks.push_time(time);
+            position += open + close + 1;
+        }
+        Ok((singer, marks))
+    }
+
+    /// SubRip cues, each one a line
+    fn srt(text: &str) -> Result<LyricsJSON, ParseError> {
+        let mut lyrics = LyricsJSON::new(SyncLevel::Line);
+        for block in Self::blocks(text) {
+            let timing = match block[0].1.contains("-->") {
+                true => 0,
+                false => 1,
+            };
+            let (begin, end) = Self::cue_timing(&block, timing)?;
+            let text = block[timing + 1..]
+                .iter()
+                .map(|(_, text)| Self::strip_tags(text))
+                .collect::<Vec<String>>()
+                .join(" ");
+            lyrics.add_line(Line::new(Some(begin), Some(end), text.trim().to_string()));
+        }
+        Ok(lyrics)
+    }
+
+    /// WebVTT cues, inline timestamps become words and `<v Name>` the singer
+    fn vtt(text: &str) -> Result<LyricsJSON, ParseError> {
+        let mut blocks = Self::blocks(text).into_iter();
+        match blocks.next() {
+            Some(header) if header[0].1.starts_with("WEBVTT") => (),
+            _ => Err(ParseError::new(1, 1, "missing WEBVTT header"))?,
+        }
+        let mut lyrics = LyricsJSON::new(SyncLevel::Line);
+        for block in blocks {
+            let first = block[0].1.trim_start();
+            if ["NOTE", "STYLE", "REGION"]
+                .iter()
+                .any(|kind| first.starts_with(kind))
+            {
+                continue;
+            }
+            let timing = match first.contains("-->") {
+                true => 0,
+                false => 1,
+            };
+            let (begin, end) = Self::cue_timing(&block, timing)?;
+            let (singer, marks) = Self::vtt_marks(&block[timing + 1..], begin)?;
+            let mut line = Line::new(Some(begin), Some(end), marks.text());
+            if marks.marks.len() > 1 {
+                line.words = marks.words(Some(end));
+                lyrics.sync = SyncLevel::Syllable;
+            }
+            if let Some(singer) = singer {
+                if lyrics.singer(&singer).is_none() {
+                    lyrics.singers.push(Singer {
+                        id: singer.clone(),
+                        name: Some(singer.clone()),
+                        kind: SingerKind::Person,
+                    });
+                }
+                line.singer = Some(singer);
+            }
+            lyrics.add_line(line);
+        }
+        Ok(lyrics)
+    }
+
+    /// Cue text starting at `begin`, other tags like `<c>` and `<i>` are dropped
+    fn vtt_marks(
+        payload: &[(usize, &str)],
+        begin: Timestamp,
+    ) -> Result<(Option<String>, Marks), ParseError> {
+        let mut singer = None;
+        let mut marks = Marks::new();
+        marks.push_time(begin);
+        for (index, &(number, raw)) in payload.iter().enumerate() {
+            if index > 0 {
+                marks.push_text(" ");
+            }
+            let mut position = 0;
+            while position < raw.len() {
+                let rest = &raw[position..];
+                let Some(open) = rest.find('<') else {
+                    marks.push_text(&Self::unescape_vtt(rest));
+                    break;
+                };
+                marks.push_text(&Self::unescape_vtt(&rest[..open]));
+                let close = rest[open..].find('>').ok_or_else(|| {
+                    ParseError::new(number, Self::column(raw, position + open), "unclosed tag")
+                })?;
+                let tag = &rest[open + 1..open + close];
+                if tag.starts_with(|c: char| c.is_ascii_digit()) {
+                    let time = Timestamp::parse(tag).map_err(|error| {
+                        ParseError::new(number, Self::column(raw, position + open + 1), error)
+                    })?;
+                    marks.push_time(time);
+                } else if let Some(voice) = tag.strip_prefix('v') {
+                    // `<v Name>` or `<v.class Name>`
+                    if let Some((_, name)) = voice.split_once(' ') {
+                        singer = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+                    }
+                }
+                position += open + close + 1;
+            }
+        }
+        Ok((singer, marks))
+    }
+
+    /// Apple or generic TTML, word timed when every line is made of timed spans
+    fn ttml(text: &str) -> Result<LyricsJSON, ParseError> {
+        Self::check_xml(text)?;
+        let synced: Option<SynedLyricXML> = quick_xml::de::from_str(text)
+            .ok()
+            .filter(Response::has_timed_spans);
+        let plain: Option<LyricXML> = match synced {
+            Some(_) => None,
+            None => Some(
+                quick_xml::de::from_str(text)
+                    .map_err(|error| ParseError::new(1, 1, error.to_string()))?,
+            ),
+        };
+        Response::convert_ttml(synced.as_ref(), plain.as_ref(), false, None)
+            .map_err(|error| ParseError::new(1, 1, error))
+    }
+
+    /// Report where the xml breaks, serde errors don't have a position
+    fn check_xml(text: &str) -> Result<(), ParseError> {
+        let mut reader = Reader::from_str(text);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Eof) => return Ok(()),
+                Ok(_) => (),
+                Err(error) => {
+                    return Err(ParseError::at_offset(
+                        text,
+                        reader.buffer_position(),
+                        error.to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Start and end of the cue at `block[index]`, like `00:01.000 --> 00:02.000 line:0`
+    fn cue_timing(
+        block: &[(usize, &str)],
+        index: usize,
+    ) -> Result<(Timestamp, Timestamp), ParseError> {
+        let (number, raw) = match block.get(index) {
+            Some(&(number, raw)) if raw.contains("-->") => (number, raw),
+            _ => Err(ParseError::new(block[0].0, 1, "missing cue timing"))?,
+        };
+        let arrow = raw.find("-->").unwrap_or_default();
+        let parse = |start: usize, time: &str| {
+            Timestamp::parse(&time.replace(',', "."))
+                .map_err(|error| ParseError::new(number, Self::column(raw, start), error))
+        };
+        let begin = raw[..arrow].trim();
+        let begin = parse(raw.len() - raw.trim_start().len(), begin)?;
+        let after = &raw[arrow + 3..];
+        let end_start = arrow + 3 + after.len() - after.trim_start().len();
+        let end = after.split_whitespace().next().unwrap_or_default();
+        let end = parse(end_start, end)?;
+        if end < begin {
+            Err(ParseError::new(
+                number,
+                Self::column(raw, end_start),
+                "cue ends before it begins",
+            ))?
+        }
+        Ok((begin, end))
+    }
+
+    /// Groups of non-empty lines with their line numbers
+    fn blocks(text: &str) -> Vec<Vec<(usize, &str)>> {
+        let mut blocks: Vec<Vec<(usize, &str)>> = Vec::new();
+        let mut block = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.trim().is_empty() {
+                if !block.is_empty() {
+                    blocks.push(std::mem::take(&mut block));
+                }
+            } else {
+                block.push((index + 1, line));
+            }
+        }
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn strip_tags(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut in_tag = false;
+        for c in text.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => in_tag = false,
+                _ if !in_tag => stripped.push(c),
+                _ => (),
+            }
+        }
+        stripped
+    }
+
+    fn unescape_vtt(text: &str) -> String {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&nbsp;", " ")
+            .replace("&lrm;", "")
+            .replace("&rlm;", "")
+            .replace("&amp;", "&")
+    }
+
+    fn column(line: &str, offset: usize) -> usize {
+        line[..offset].chars().count() + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::lyric_json::SyncLevel;
+    use crate::models::timestamp::{TimeFormat, Timestamp};
+    use crate::services::export_handler::{Export, LrcOptions};
+    use crate::services::import_handler::{Import, ImportFormat, ParseError};
+
+    #[test]
+    fn lrc_import() {
+        let text = "[ti:Song]\n[ar:Artist]\n[au:Writer A, Writer B]\n[offset:500]\n\
+                    [00:02.00]v1:<00:02.00>Hello <00:02.50>world<00:03.00>\n\
+                    [00:04.00][00:08.00]Again\n";
+        let lyrics = Import::parse(text, None).unwrap();
+        let time = |millis| Some(Timestamp::from_millis(millis));
+        assert_eq!(SyncLevel::Syllable, lyrics.sync);
+        assert_eq!(Some("Song".to_string()), lyrics.metadata.title);
+        assert_eq!(vec!["Writer A", "Writer B"], lyrics.credits.songwriters);
+        assert_eq!(3, lyrics.lines.len());
+        let first = &lyrics.lines[0];
+        assert_eq!(
+            ("Hello world", time(1500), time(2500)),
+            (first.text.as_str(), first.begin, first.end)
+        );
+        assert_eq!(Some("v1".to_string()), first.singer);
+        assert_eq!(
+            vec!["Hello ", "world"],
+            first
+                .words
+                .iter()
+                .map(|word| word.text.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(time(2500), Some(first.words[1].end));
+        assert_eq!(
+            (time(3500), time(7500)),
+            (lyrics.lines[1].begin, lyrics.lines[1].end)
+        );
+        assert_eq!(time(7500), lyrics.lines[2].begin);
+
+        let error = Import::parse("[00:01.00]fine\n[00:xx]broken", Some(ImportFormat::Lrc));
+        assert_eq!(
+            Err(ParseError {
+                line: 2,
+                column: 2,
+                message: "invalid time \"00:xx\"".to_string()
+            }),
+            error.map(|_| ())
+        );
+    }
+
+    #[test]
+    fn lrc_section_markers() {
+        let text = "[Chorus]\n[00:01.00]Hello\n[instrumental]\n[00:02.00][Verse 2]Again\n";
+        let lyrics = Import::parse(text, None).unwrap();
+        assert_eq!(SyncLevel::Line, lyrics.sync);
+        assert_eq!(
+            vec!["Hello", "Again"],
+            lyrics
+                .lines
+                .iter()
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn lrc_round_trip() {
+        let text = "[00:01.50]<00:01.50>Hello <00:02.00>world<00:03.00>\n[00:04.00]<00:04.00>Bye<00:05.00>\n";
+        let lyrics = Import::parse(text, None).unwrap();
+        let exported = Export::lrc(&lyrics, &LrcOptions::default());
+        let again = Import::parse(&exported, None).unwrap();
+        assert_eq!(
+            lyrics.to_value(TimeFormat::Millis),
+            again.to_value(TimeFormat::Millis)
+        );
+    }
+
+    #[test]
+    fn subtitles_import() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,500\n<i>Hello</i>\nworld\n\n2\n00:00:03,000 --> 00:00:04,000\nAgain\n";
+        assert_eq!(ImportFormat::Srt, ImportFormat::detect(srt));
+        let lyrics = Import::parse(srt, None).unwrap();
+        assert_eq!(SyncLevel::Line, lyrics.sync);
+        assert_eq!("Hello world", lyrics.lines[0].text);
+        assert_eq!(Some(Timestamp::from_millis(2500)), lyrics.lines[0].end);
+
+        let vtt = "WEBVTT\n\nNOTE Written by Siren\n\nintro\n00:01.000 --> 00:03.000 align:start\n\
+                   <v Ann>Hello <00:02.000>rock &amp; roll\n";
+        let lyrics = Import::parse(vtt, None).unwrap();
+        let line = &lyrics.lines[0];
+        assert_eq!(SyncLevel::Syllable, lyrics.sync);
+        assert_eq!("Hello rock & roll", line.text);
+        assert_eq!(Some("Ann".to_string()), line.singer);
+        assert_eq!(Some("Ann".to_string()), lyrics.singers[0].name);
+        assert_eq!(Timestamp::from_millis(3000), line.words[1].end);
+
+        let error = Import::parse("WEBVTT\n\n00:01.000 --> 00:0x.000\nHello", None);
+        assert_eq!(
+            Some((3, 15)),
+            error.err().map(|error| (error.line, error.column))
+        );
+    }
+
+    #[test]
+    fn ttml_error_position() {
+        let ttml = "<tt>\n  <body>\n    <p begin=\"1\">Hello</span>\n</tt>";
+        let error = Import::parse(ttml, None).err().unwrap();
+        assert_eq!(3, error.line);
+    }
+}
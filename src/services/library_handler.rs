@@ -0,0 +1,245 @@
+use crate::models::lyric_json::LyricsJSON;
+use crate::services::export_handler::LyricsFormat;
+use crate::services::import_handler::{Import, ImportFormat};
+use crate::services::response_handler::Response;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions looked up for every key, the richest formats first
+const EXTENSIONS: [(&str, ImportFormat); 6] = [
+    ("json", ImportFormat::Json),
+    ("ttml", ImportFormat::Ttml),
+    ("xml", ImportFormat::Ttml),
+    ("lrc", ImportFormat::Lrc),
+    ("vtt", ImportFormat::Vtt),
+    ("srt", ImportFormat::Srt),
+];
+/// Apple Music responses saved by Siren, with every translation and romanization
+const CACHE_SUFFIX: &str = "apple";
+/// Characters that can't be part of file names
+const RESERVED_CHARACTERS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Directory of lyrics files checked before Apple Music
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    pub path: PathBuf,
+    /// Save responses from Apple Music as `<id>.apple.json`, so the library works as an offline cache
+    pub save: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("lyrics"),
+            save: false,
+        }
+    }
+}
+
+impl LibraryConfig {
+    /// Errors are prefixed with the config path
+    pub(crate) fn validate(&self, prefix: &str, errors: &mut Vec<String>) {
+        if self.path.as_os_str().is_empty() {
+            errors.push(format!("{}path is empty", prefix));
+        }
+    }
+}
+
+/// Names a song can have in the library
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SongKeys {
+    pub song_id: Option<String>,
+    pub isrc: Option<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl SongKeys {
+    /// File names without extension, catalog id first, then ISRC, then `artist - title`
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Some(song_id) = &self.song_id {
+            names.push(Library::normalise(song_id));
+        }
+        if let Some(isrc) = &self.isrc {
+            names.push(Library::normalise(isrc).to_uppercase());
+        }
+        if let (Some(artist), Some(title)) = (&self.artist, &self.title) {
+            names.push(format!(
+                "{} - {}",
+                Library::normalise(artist),
+                Library::normalise(title)
+            ));
+        }
+        names.retain(|name| !name.is_empty() && name != " - ");
+        names
+    }
+}
+
+pub struct Library {}
+
+impl Library {
+    /// First file in the library for any of the keys
+    pub(crate) fn find(config: &LibraryConfig, keys: &SongKeys) -> Option<(PathBuf, ImportFormat)> {
+        keys.names().into_iter().find_map(|name| {
+            EXTENSIONS.iter().find_map(|(extension, format)| {
+                let path = config.path.join(format!("{}.{}", name, extension));
+                path.is_file().then_some((path, *format))
+            })
+        })
+    }
+
+    /// Lyrics from the library, `None` when it has no file for the song.
+    /// Files put there by hand win over saved Apple Music responses, which are
+    /// converted with `space` and `lang` like a fresh response
+    pub(crate) fn load(
+        config: &LibraryConfig,
+        keys: &SongKeys,
+        space: bool,
+        lang: Option<&str>,
+    ) -> Result<Option<LyricsJSON>, String> {
+        if let Some((path, format)) = Self::find(config, keys) {
+            let text = Self::read(&path)?;
+            return Import::parse(&text, Some(format))
+                .map(Some)
+                .map_err(|error| format!("{}: {}", path.display(), error));
+        }
+        let path = match &keys.song_id {
+            Some(song_id) => Self::cache_path(config, song_id),
+            None => return Ok(None),
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let text = Self::read(&path)?;
+        Response::extract_lyrics_to_json(&text, space, lang)
+            .map(Some)
+            .map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Save an Apple Music response under the catalog id of the song, replacing
+    /// a saved response that could not be read
+    pub(crate) fn save(
+        config: &LibraryConfig,
+        song_id: &str,
+        response: &str,
+    ) -> Result<PathBuf, String> {
+        fs::create_dir_all(&config.path)
+            .map_err(|error| format!("Unable to create {}: {}", config.path.display(), error))?;
+        let name = format!("{}.{}", Self::normalise(song_id), CACHE_SUFFIX);
+        let name = Self::path_name(&config.path.join(name))?;
+        Response::create_file(response, &name, LyricsFormat::Json)?;
+        Ok(Self::cache_path(config, song_id))
+    }
+
+    fn cache_path(config: &LibraryConfig, song_id: &str) -> PathBuf {
+        config.path.join(format!(
+            "{}.{}.{}",
+            Self::normalise(song_id),
+            CACHE_SUFFIX,
+            LyricsFormat::Json.extension()
+        ))
+    }
+
+    fn read(path: &Path) -> Result<String, String> {
+        fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read {}: {}", path.display(), error))
+    }
+
+    /// Lowercase with single spaces and no characters reserved in file names
+    pub(crate) fn normalise(name: &str) -> String {
+        name.to_lowercase()
+            .replace(RESERVED_CHARACTERS, "")
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    fn path_name(path: &Path) -> Result<String, String> {
+        path.to_str()
+            .map(|path| path.to_string())
+            .ok_or_else(|| format!("{} is not valid unicode", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::services::library_handler::{Library, LibraryConfig, SongKeys};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn song_keys() {
+        let keys = SongKeys {
+            song_id: Some("1440833098".to_string()),
+            isrc: Some("usum71900764".to_string()),
+            artist: Some("AC/DC".to_string()),
+            title: Some("  Back   in Black ".to_string()),
+        };
+        assert_eq!(
+            vec!["1440833098", "USUM71900764", "acdc - back in black"],
+            keys.names()
+        );
+        let keys = SongKeys {
+            artist: Some("Artist".to_string()),
+            ..Default::default()
+        };
+        assert!(keys.names().is_empty());
+    }
+
+    #[test]
+    fn library_override_and_cache() {
+        let path = std::env::temp_dir().join(format!("siren-library-{}", std::process::id()));
+        let config = LibraryConfig {
+            path: path.clone(),
+            save: true,
+        };
+        let keys = SongKeys {
+            song_id: Some("1".to_string()),
+            artist: Some("Artist".to_string()),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        assert!(Library::load(&config, &keys, false, None)
+            .unwrap()
+            .is_none());
+
+        let ttml = r#"<tt><head><metadata><iTunesMetadata><translations><translation xml:lang="fr"><text for="L1">Bonjour</text></translation><translation xml:lang="zh"><text for="L1">你好</text></translation></translations></iTunesMetadata></metadata></head><body><div><p begin="1" end="2" itunes:key="L1">Hello</p></div></body></tt>"#;
+        let response = json!({ "data": [{
+            "attributes": { "name": "Song", "artistName": "Artist", "durationInMillis": 2000 },
+            "relationships": { "lyrics": {
+                "href": "",
+                "data": [{ "id": "1", "type": "lyrics", "attributes": { "ttml": ttml } }],
+            } },
+        }] })
+        .to_string();
+        let saved = Library::save(&config, "1", &response).unwrap();
+        assert_eq!(path.join("1.apple.json"), saved);
+        let lyrics = Library::load(&config, &keys, false, None).unwrap().unwrap();
+        assert_eq!("Hello", lyrics.lines[0].text);
+        assert_eq!(vec!["fr", "zh"], lyrics.translations);
+        assert_eq!(Some("Bonjour"), lyrics.lines[0].translation.as_deref());
+        let lyrics = Library::load(&config, &keys, false, Some("zh"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("你好"), lyrics.lines[0].translation.as_deref());
+
+        fs::write(path.join("artist - song.lrc"), "[00:01.00]Corrected\n").unwrap();
+        let lyrics = Library::load(&config, &keys, false, None).unwrap().unwrap();
+        assert_eq!("Corrected", lyrics.lines[0].text);
+        fs::write(path.join("1.json"), "{ broken").unwrap();
+        let error = Library::load(&config, &keys, false, None).err().unwrap();
+        assert!(error.contains("line 1, column 3"), "{}", error);
+
+        fs::remove_file(path.join("1.json")).unwrap();
+        fs::remove_file(path.join("artist - song.lrc")).unwrap();
+        fs::write(&saved, "{ broken").unwrap();
+        assert!(Library::load(&config, &keys, false, None).is_err());
+        Library::save(&config, "1", &response).unwrap();
+        let lyrics = Library::load(&config, &keys, false, None).unwrap().unwrap();
+        assert_eq!("Hello", lyrics.lines[0].text);
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
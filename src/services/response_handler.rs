@@ -1,128 +1,373 @@
 use crate::models::apple_music::AppleMusic;
-use crate::models::lyric_json::{Line, LyricsJSON, Word};
-use crate::models::lyric_xml::LyricXML;
+use crate::models::lyric_json::{
+    Credits, Line, LyricsJSON, Metadata, Singer, SingerKind, SyncLevel, Word,
+};
+use crate::models::lyric_xml::{Head, LyricXML, TextContent, Translation, P as LyricP};
 use crate::models::synced_lyric_xml::SynedLyricXML;
+use crate::models::timestamp::Timestamp;
+use crate::services::export_handler::LyricsFormat;
 use std::char;
 use std::{fs::File, io::Write};
 
 pub struct Response {}
 
+/// A lyric line with its begin and end
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedLine {
+    pub begin: Timestamp,
+    pub end: Timestamp,
+    pub text: String,
+}
+
+impl TimedLine {
+    /// The line being sung `elapsed` milliseconds into the song
+    pub(crate) fn at(lines: &[TimedLine], elapsed: u64) -> Option<&str> {
+        lines
+            .iter()
+            .find(|line| line.begin.as_millis() <= elapsed && elapsed < line.end.as_millis())
+            .map(|line| line.text.as_str())
+    }
+}
+
 impl Response {
-    /// Create and save data to json file
-    pub(crate) fn create_file(data: &str, name: &str, extension: &str) {
-        let name: String = format!("{}.{}", name, extension);
-        let mut file = File::create(name).unwrap();
+    /// Save exported lyrics to `name` with the extension of the format
+    pub(crate) fn create_file(data: &str, name: &str, format: LyricsFormat) -> Result<(), String> {
+        let name: String = format!("{}.{}", name, format.extension());
+        let mut file =
+            File::create(&name).map_err(|error| format!("Unable to create {}: {}", name, error))?;
         file.write_all(data.as_bytes())
-            .expect("Unable write data to file");
+            .map_err(|error| format!("Unable to write {}: {}", name, error))
     }
 
-    pub(crate) fn extract_lyrics_to_json(text: &str, space: bool) -> Result<LyricsJSON, String> {
-        println!("{}", text);
-        let mut lyrics = LyricsJSON::new();
+    /// Best lyrics available: syllable synced, then line synced, then plain text,
+    /// with the translation in `lang`, or the first one
+    pub(crate) fn extract_lyrics_to_json(
+        text: &str,
+        space: bool,
+        lang: Option<&str>,
+    ) -> Result<LyricsJSON, String> {
         let response_json: AppleMusic =
             serde_json::from_str(text).map_err(|error| error.to_string())?;
+        let lyric_xml: Option<LyricXML> = Self::extract_lyric_xml(&response_json).ok();
+        let synced_lyric_xml: Option<SynedLyricXML> =
+            Self::extract_syned_lyric_xml(&response_json).ok();
 
-        let synced_lyric_xml: SynedLyricXML = Self::extract_syned_lyric_xml(&response_json)?;
-        let lyric_xml: LyricXML = Self::extract_lyric_xml(&response_json)?;
-
-        if space {
-            Self::convert_to_json_with_space(&synced_lyric_xml, &lyric_xml, &mut lyrics);
-        } else {
-            Self::convert_to_json(&synced_lyric_xml, &mut lyrics);
-        }
-
+        let mut lyrics =
+            Self::convert_ttml(synced_lyric_xml.as_ref(), lyric_xml.as_ref(), space, lang)?;
+        lyrics.metadata = Self::extract_metadata(&response_json);
         Ok(lyrics)
     }
 
-    pub(crate) fn extract_lyrics_to_lrc(text: &str) -> Result<String, String> {
-        let mut lrc: String = String::new();
-        let response_json: AppleMusic =
-            serde_json::from_str(text).map_err(|error| error.to_string())?;
+    /// Json from syllable and line lyrics xml, either of them may be missing
+    pub(crate) fn convert_ttml(
+        synced_lyric_xml: Option<&SynedLyricXML>,
+        lyric_xml: Option<&LyricXML>,
+        space: bool,
+        lang: Option<&str>,
+    ) -> Result<LyricsJSON, String> {
+        // Syllable lyrics with a span missing its timing or text fall back to lines
+        let synced_lyric_xml = synced_lyric_xml.filter(|xml| Self::has_timed_spans(xml));
+        let (mut lyrics, keys) = match (synced_lyric_xml, lyric_xml) {
+            (Some(synced_lyric_xml), _) => {
+                let mut lyrics = LyricsJSON::new(SyncLevel::Syllable);
+                lyrics.singers = Self::extract_singers(&synced_lyric_xml.head);
+                match (lyric_xml, space) {
+                    (Some(lyric_xml), true) => {
+                        Self::convert_to_json_with_space(synced_lyric_xml, lyric_xml, &mut lyrics)
+                    }
+                    _ => Self::convert_to_json(synced_lyric_xml, lyric_xml, &mut lyrics),
+                }
+                let keys: Vec<Option<&str>> = synced_lyric_xml
+                    .body
+                    .div
+                    .iter()
+                    .flat_map(|div| &div.p)
+                    .map(|p| p.key.as_deref())
+                    .collect();
+                (lyrics, keys)
+            }
+            (None, Some(lyric_xml)) => {
+                let keys: Vec<Option<&str>> = lyric_xml
+                    .body
+                    .div
+                    .iter()
+                    .flat_map(|div| &div.p)
+                    .map(|p| p.key.as_deref())
+                    .collect();
+                (Self::convert_lines_to_json(lyric_xml), keys)
+            }
+            (None, None) => Err("no lyrics found".to_string())?,
+        };
+        let heads: Vec<&Head> = [
+            synced_lyric_xml.map(|xml| &xml.head),
+            lyric_xml.map(|xml| &xml.head),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        Self::add_translations(&mut lyrics, &heads, &keys, lang);
+        lyrics.credits = Self::extract_credits(&heads);
+        Ok(lyrics)
+    }
 
-        let synced_lyric_xml: SynedLyricXML = Self::extract_syned_lyric_xml(&response_json)?;
+    /// Every paragraph has spans, all of them with begin, end and text
+    pub(crate) fn has_timed_spans(xml: &SynedLyricXML) -> bool {
+        let mut paragraphs = xml.body.div.iter().flat_map(|div| &div.p).peekable();
+        paragraphs.peek().is_some()
+            && paragraphs.all(|p| {
+                !p.span.is_empty()
+                    && p.span.iter().all(|span| match &span.span {
+                        Some(background) => background.iter().all(|span| {
+                            span.begin.is_some() && span.end.is_some() && span.word.is_some()
+                        }),
+                        None => span.begin.is_some() && span.end.is_some() && span.word.is_some(),
+                    })
+            })
+    }
 
-        Self::convert_to_lrc(&synced_lyric_xml, &mut lrc);
-        Ok(lrc)
+    /// Songwriters from the head of the syllable or the line lyrics
+    fn extract_credits(heads: &[&Head]) -> Credits {
+        let songwriters = heads
+            .iter()
+            .map(|head| &head.metadata.itunes.songwriters.songwriter)
+            .find(|songwriters| !songwriters.is_empty())
+            .map(|songwriters| {
+                songwriters
+                    .iter()
+                    .map(|songwriter| songwriter.trim().to_string())
+                    .filter(|songwriter| !songwriter.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Credits { songwriters }
     }
 
-    fn convert_to_lrc(synced_lyric_xml: &SynedLyricXML, lrc: &mut String) {
-        let synced_lyric_array = &synced_lyric_xml.body.div;
-        for div in synced_lyric_array {
-            for p in &div.p {
-                let line_start_time = Self::convert_time_to_lrc_format(&p.begin);
-                let mut line: String = format!("[{}] ", line_start_time);
-                for span in &p.span {
-                    let span = span.clone();
-                    match span.span {
-                        Some(background) => {
-                            for word in background {
-                                let word_start_time =
-                                    Self::convert_time_to_lrc_format(&word.begin.unwrap());
-                                let word: String =
-                                    format!("<{}> {} ", word_start_time, word.word.unwrap());
-                                line.push_str(&word);
-                            }
-                        }
-                        None => {
-                            let word_start_time =
-                                Self::convert_time_to_lrc_format(&span.begin.unwrap());
-                            let word: String =
-                                format!("<{}> {} ", word_start_time, span.word.unwrap());
-                            line.push_str(&word);
-                        }
-                    }
+    /// Translation and romanization of every line, matched by its `itunes:key`.
+    /// Either may be in the head of the syllable or of the line lyrics
+    fn add_translations(
+        lyrics: &mut LyricsJSON,
+        heads: &[&Head],
+        keys: &[Option<&str>],
+        lang: Option<&str>,
+    ) {
+        let translations: Vec<&Translation> = heads
+            .iter()
+            .map(|head| &head.metadata.itunes.translations.translation)
+            .find(|translations| !translations.is_empty())
+            .map(|translations| translations.iter().collect())
+            .unwrap_or_default();
+        let transliterations: Vec<&Translation> = heads
+            .iter()
+            .map(|head| &head.metadata.itunes.transliterations.transliteration)
+            .find(|transliterations| !transliterations.is_empty())
+            .map(|transliterations| transliterations.iter().collect())
+            .unwrap_or_default();
+        lyrics.translations = translations
+            .iter()
+            .filter_map(|translation| translation.lang.clone())
+            .collect();
+        let translation = match lang {
+            Some(_) => Self::pick_language(&translations, lang),
+            None => translations.first().copied(),
+        };
+        lyrics.language = translation.and_then(|translation| translation.lang.clone());
+        // Usually there is only one script to romanize to
+        let transliteration =
+            Self::pick_language(&transliterations, lang).or(transliterations.first().copied());
+
+        for (line, key) in lyrics.lines.iter_mut().zip(keys) {
+            let Some(key) = key else {
+                continue;
+            };
+            if let Some(content) = Self::translated_line(translation, key) {
+                line.translation = Some(Self::content_text(content).trim().to_string());
+            }
+            if let Some(content) = Self::translated_line(transliteration, key) {
+                line.romanization = Some(Self::content_text(content).trim().to_string());
+                let mut spans = Vec::new();
+                Self::content_spans(content, &mut spans);
+                for word in line.words.iter_mut().chain(line.background.iter_mut()) {
+                    word.romanization = spans
+                        .iter()
+                        .find(|(begin, _)| *begin == word.begin)
+                        .map(|(_, text)| text.clone());
                 }
-                line.push('\n');
-                lrc.push_str(&line);
             }
         }
     }
 
-    fn convert_time_to_lrc_format(time: &str) -> String {
-        let mut min: String = String::new();
-        let mut sec: String = String::new();
-        let mut ms: String = String::new();
-
-        let mut temp: String = String::new();
-        for char in time.chars() {
-            match char {
-                ':' => {
-                    min = temp.clone();
-                    temp.clear();
-                }
-                '.' => {
-                    sec = temp.clone();
-                    temp.clear();
+    fn translated_line<'a>(
+        translation: Option<&'a Translation>,
+        key: &str,
+    ) -> Option<&'a [TextContent]> {
+        translation?
+            .text
+            .iter()
+            .find(|text| text.key == key)
+            .map(|text| text.content.as_slice())
+    }
+
+    /// `lang` exactly, or by its primary subtag like `en` for `en-US`
+    fn pick_language<'a>(
+        translations: &[&'a Translation],
+        lang: Option<&str>,
+    ) -> Option<&'a Translation> {
+        let lang = lang?.to_lowercase();
+        let primary = |lang: &str| lang.split('-').next().unwrap_or_default().to_string();
+        let lang_of = |translation: &Translation| translation.lang.clone().unwrap_or_default();
+        translations
+            .iter()
+            .find(|translation| lang_of(translation).to_lowercase() == lang)
+            .or_else(|| {
+                translations.iter().find(|translation| {
+                    primary(&lang_of(translation).to_lowercase()) == primary(&lang)
+                })
+            })
+            .copied()
+    }
+
+    fn content_text(content: &[TextContent]) -> String {
+        content
+            .iter()
+            .map(|content| match content {
+                TextContent::Text(text) => text.clone(),
+                TextContent::Span(span) => Self::content_text(&span.content),
+            })
+            .collect()
+    }
+
+    /// Timed spans with their text, for word level romanization
+    fn content_spans(content: &[TextContent], spans: &mut Vec<(Timestamp, String)>) {
+        for content in content {
+            if let TextContent::Span(span) = content {
+                match span.begin {
+                    Some(begin)
+                        if !span
+                            .content
+                            .iter()
+                            .any(|c| matches!(c, TextContent::Span(_))) =>
+                    {
+                        spans.push((begin, Self::content_text(&span.content).trim().to_string()))
+                    }
+                    _ => Self::content_spans(&span.content, spans),
                 }
-                _ => temp.push(char),
             }
         }
-        if !temp.is_empty() {
-            ms = temp.clone();
-        }
+    }
 
-        if min.len() < 2 && !min.is_empty() {
-            min.insert(0, '0');
-        } else if min.is_empty() {
-            min = "00".to_string();
+    /// Title, artist, album and length of the song from the catalog
+    fn extract_metadata(json: &AppleMusic) -> Metadata {
+        match json.data.first() {
+            Some(data) => Metadata {
+                title: data.attributes.name.clone(),
+                artist: data.attributes.artist_name.clone(),
+                album: data.attributes.album_name.clone(),
+                duration: data
+                    .attributes
+                    .duration_in_millis
+                    .map(Timestamp::from_millis),
+            },
+            None => Metadata::default(),
         }
-        if sec.len() < 2 && !sec.is_empty() {
-            sec.insert(0, '0');
-        } else if sec.is_empty() {
-            sec = "00".to_string();
-        }
-        if ms.len() > 2 {
-            ms.pop();
+    }
+
+    /// Unsynced lyrics, one string per line
+    pub(crate) fn plain_lines(lyrics: &LyricsJSON) -> Vec<String> {
+        lyrics.lines.iter().map(|line| line.text.clone()).collect()
+    }
+
+    /// Lines with their timing, empty when the lyrics aren't synced
+    pub(crate) fn timed_lines(lyrics: &LyricsJSON) -> Vec<TimedLine> {
+        lyrics
+            .lines
+            .iter()
+            .filter_map(|line| {
+                Some(TimedLine {
+                    begin: line.begin?,
+                    end: line.end?,
+                    text: line.text.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Line synced lyrics, or unsynced when any line has no time
+    fn convert_lines_to_json(lyric_xml: &LyricXML) -> LyricsJSON {
+        let paragraphs: Vec<&LyricP> = lyric_xml.body.div.iter().flat_map(|div| &div.p).collect();
+        let synced = paragraphs
+            .iter()
+            .all(|p| p.begin.is_some() && p.end.is_some());
+        let mut lyrics = LyricsJSON::new(if synced {
+            SyncLevel::Line
+        } else {
+            SyncLevel::Unsynced
+        });
+        lyrics.singers = Self::extract_singers(&lyric_xml.head);
+        for p in paragraphs {
+            let mut line = match synced {
+                true => Line::new(p.begin, p.end, p.line.clone()),
+                false => Line::new(None, None, p.line.clone()),
+            };
+            line.singer = p.agent.clone();
+            lyrics.add_line(line);
         }
-        format!("{}:{}.{}", min, sec, ms)
+        lyrics
+    }
+
+    /// Singers declared as `ttm:agent`, with their full name when there is one
+    fn extract_singers(head: &Head) -> Vec<Singer> {
+        head.metadata
+            .agent
+            .iter()
+            .map(|agent| Singer {
+                id: agent.id.clone(),
+                name: agent
+                    .name
+                    .iter()
+                    .find(|name| name.name_type.as_deref() == Some("full"))
+                    .or_else(|| agent.name.first())
+                    .map(|name| name.name.trim().to_string())
+                    .filter(|name| !name.is_empty()),
+                kind: match agent.agent_type.as_deref() {
+                    Some("person") => SingerKind::Person,
+                    Some("group") => SingerKind::Group,
+                    _ => SingerKind::Other,
+                },
+            })
+            .collect()
+    }
+
+    /// Text of the same line in the plain lyrics, which has the spaces between words
+    fn plain_line(lyric_xml: Option<&LyricXML>, div_index: usize, p_index: usize) -> Option<&str> {
+        lyric_xml?
+            .body
+            .div
+            .get(div_index)?
+            .p
+            .get(p_index)
+            .map(|p| p.line.as_str())
     }
 
     /// Convert syned lyrics xml to json format
-    fn convert_to_json(synced_lyric_xml: &SynedLyricXML, lyrics: &mut LyricsJSON) {
+    fn convert_to_json(
+        synced_lyric_xml: &SynedLyricXML,
+        lyric_xml: Option<&LyricXML>,
+        lyrics: &mut LyricsJSON,
+    ) {
         let synced_lyric_array = &synced_lyric_xml.body.div;
-        for div in synced_lyric_array {
-            for p in &div.p {
-                let mut line: Line = Line::new(p.begin.clone(), p.end.clone());
+        for (div_index, div) in synced_lyric_array.iter().enumerate() {
+            for (p_index, p) in div.p.iter().enumerate() {
+                let text = Self::plain_line(lyric_xml, div_index, p_index)
+                    .map(|line| line.to_string())
+                    .unwrap_or_else(|| {
+                        p.span
+                            .iter()
+                            .filter_map(|span| span.word.clone())
+                            .collect::<Vec<String>>()
+                            .join("")
+                    });
+                let mut line: Line = Line::new(Some(p.begin), Some(p.end), text);
+                line.singer = p.agent.clone();
                 for span in &p.span {
                     let span = span.clone();
                     match span.span {
@@ -158,15 +403,14 @@ impl Response {
         lyrics: &mut LyricsJSON,
     ) {
         let synced_lyric_array = &synced_lyric_xml.body.div;
-        let lyric_array = &lyric_xml.body.div;
         for (div_index, div) in synced_lyric_array.iter().enumerate() {
             for (p_index, p) in div.p.iter().enumerate() {
-                let mut line: Line = Line::new(p.begin.clone(), p.end.clone());
-                let lyric_chars: Vec<char> = lyric_array[div_index].p[p_index]
-                    .line
-                    .clone()
-                    .chars()
-                    .collect();
+                let plain_line = Self::plain_line(Some(lyric_xml), div_index, p_index)
+                    .unwrap_or_default()
+                    .to_string();
+                let lyric_chars: Vec<char> = plain_line.chars().collect();
+                let mut line: Line = Line::new(Some(p.begin), Some(p.end), plain_line);
+                line.singer = p.agent.clone();
                 let mut lyric_char_index: usize = 0;
                 for span in &p.span {
                     let span = span.clone();
@@ -196,6 +440,9 @@ impl Response {
                         }
                     }
                 }
+                if line.text.is_empty() {
+                    line.text = line.words.iter().map(|word| word.text.as_str()).collect();
+                }
                 lyrics.add_line(line);
             }
         }
@@ -210,7 +457,6 @@ impl Response {
     ) {
         for c in synced_lyric_chars {
             if *lyric_char_index >= lyric_chars.len() {
-                println!("{:}", word);
                 return;
             }
             if c == lyric_chars[*lyric_char_index] {
@@ -261,3 +507,142 @@ impl Response {
         Ok(output)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::models::lyric_json::{SingerKind, SyncLevel};
+    use crate::models::timestamp::{TimeFormat, Timestamp};
+    use crate::services::response_handler::{Response, TimedLine};
+    use serde_json::json;
+
+    fn apple_music(syllable_ttml: &str, ttml: &str) -> String {
+        let lyrics = |ttml: &str| {
+            json!({
+                "href": "",
+                "data": [{ "id": "1", "type": "lyrics", "attributes": { "ttml": ttml } }],
+            })
+        };
+        let mut relationships = json!({ "lyrics": lyrics(ttml) });
+        if !syllable_ttml.is_empty() {
+            relationships["syllable-lyrics"] = lyrics(syllable_ttml);
+        }
+        json!({ "data": [{
+            "attributes": { "name": "Song", "artistName": "Artist", "durationInMillis": 4500 },
+            "relationships": relationships,
+        }] })
+        .to_string()
+    }
+
+    #[test]
+    fn fallback_sync_level() {
+        let text = apple_music(
+            "",
+            r#"<tt><body><div><p begin="1.5" end="3">Hello world</p><p begin="3" end="4.25">Again</p></div></body></tt>"#,
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, true, None).unwrap();
+        assert_eq!(SyncLevel::Line, lyrics.sync);
+        assert_eq!(Some(Timestamp::from_millis(1500)), lyrics.lines[0].begin);
+        assert_eq!("Again", lyrics.lines[1].text);
+        assert_eq!(Some("Artist".to_string()), lyrics.metadata.artist);
+        assert_eq!(Some(Timestamp::from_millis(4500)), lyrics.metadata.duration);
+        assert_eq!(2, Response::timed_lines(&lyrics).len());
+
+        let text = apple_music(
+            "",
+            r#"<tt><body><div><p>Hello world</p><p>Again</p></div></body></tt>"#,
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, true, None).unwrap();
+        assert_eq!(SyncLevel::Unsynced, lyrics.sync);
+        assert!(lyrics.lines.iter().all(|line| line.begin.is_none()));
+        assert_eq!(
+            json!("unsynced"),
+            lyrics.to_value(TimeFormat::Millis)["sync"]
+        );
+        assert!(Response::timed_lines(&lyrics).is_empty());
+
+        let text = apple_music(
+            r#"<tt><body><div><p begin="1.5" end="3"><span begin="1.5">Hello</span></p></div></body></tt>"#,
+            r#"<tt><body><div><p begin="1.5" end="3">Hello</p></div></body></tt>"#,
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, true, None).unwrap();
+        assert_eq!(SyncLevel::Line, lyrics.sync);
+        assert_eq!("Hello", lyrics.lines[0].text);
+
+        let text = json!({ "data": [{ "relationships": {} }] }).to_string();
+        assert!(Response::extract_lyrics_to_json(&text, true, None).is_err());
+    }
+
+    #[test]
+    fn duet_singers() {
+        let head = r#"<head><metadata><ttm:agent type="person" xml:id="v1"><ttm:name type="full">Ann</ttm:name></ttm:agent><ttm:agent type="group" xml:id="v1000"/></metadata></head>"#;
+        let text = apple_music(
+            &format!(
+                r#"<tt xmlns:ttm="http://www.w3.org/ns/ttml#metadata">{}<body><div><p begin="1" end="2" ttm:agent="v1"><span begin="1" end="2">La</span></p><p begin="2" end="3" ttm:agent="v1000"><span begin="2" end="3">La</span></p></div></body></tt>"#,
+                head
+            ),
+            "",
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, false, None).unwrap();
+        assert_eq!(Some("Ann"), lyrics.singers[0].name.as_deref());
+        assert_eq!(SingerKind::Group, lyrics.singers[1].kind);
+        assert_eq!(Some("v1"), lyrics.lines[0].singer.as_deref());
+        assert_eq!(Some("v1000"), lyrics.lines[1].singer.as_deref());
+    }
+
+    #[test]
+    fn translations() {
+        let head = r#"<head><metadata><iTunesMetadata xmlns="http://music.apple.com/lyric-ttml-internal"><translations><translation type="replacement" xml:lang="en"><text for="L1">Hello</text></translation><translation type="replacement" xml:lang="zh-Hant"><text for="L1">你好</text></translation></translations><transliterations><transliteration xml:lang="ja-Latn"><text for="L1"><span begin="1" end="1.5">kon</span><span begin="1.5" end="2">nichiwa</span></text></transliteration></transliterations><songwriters><songwriter>Ann</songwriter><songwriter>Bob</songwriter></songwriters></iTunesMetadata></metadata></head>"#;
+        let text = apple_music(
+            &format!(
+                r#"<tt>{}<body><div><p begin="1" end="2" itunes:key="L1"><span begin="1" end="1.5">こん</span><span begin="1.5" end="2">にちは</span></p></div></body></tt>"#,
+                head
+            ),
+            "",
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, false, None).unwrap();
+        assert_eq!(vec!["en", "zh-Hant"], lyrics.translations);
+        assert_eq!(Some("en"), lyrics.language.as_deref());
+        let line = &lyrics.lines[0];
+        assert_eq!(Some("Hello"), line.translation.as_deref());
+        assert_eq!(Some("konnichiwa"), line.romanization.as_deref());
+        assert_eq!(Some("nichiwa"), line.words[1].romanization.as_deref());
+        assert_eq!(vec!["Ann", "Bob"], lyrics.credits.songwriters);
+
+        let lyrics = Response::extract_lyrics_to_json(&text, false, Some("zh")).unwrap();
+        assert_eq!(Some("你好"), lyrics.lines[0].translation.as_deref());
+        let lyrics = Response::extract_lyrics_to_json(&text, false, Some("fr")).unwrap();
+        assert_eq!(None, lyrics.lines[0].translation);
+        assert_eq!(Some("konnichiwa"), lyrics.lines[0].romanization.as_deref());
+    }
+
+    #[test]
+    fn typed_timestamps() {
+        let text = apple_music(
+            r#"<tt><body><div><p begin="1:02:03.4" end="1:02:05"><span begin="1:02:03.4" end="1:02:04">Hello</span><span begin="01:02:04.25" end="3725s">world</span></p></div></body></tt>"#,
+            r#"<tt><body><div><p begin="1:02:03.4" end="1:02:05">Hello world</p></div></body></tt>"#,
+        );
+        let lyrics = Response::extract_lyrics_to_json(&text, false, None).unwrap();
+        assert_eq!("Hello", lyrics.lines[0].words[0].text);
+
+        let lyrics = Response::extract_lyrics_to_json(&text, true, None).unwrap();
+        let millis = lyrics.to_value(TimeFormat::Millis);
+        assert_eq!(3723400, millis["lines"][0]["begin"]);
+        assert_eq!(3725000, millis["lines"][0]["words"][1]["end"]);
+        let legacy = lyrics.to_value(TimeFormat::Legacy);
+        assert_eq!("1:02:03.400", legacy["lines"][0]["begin"]);
+        assert_eq!("Hello ", legacy["lines"][0]["words"][0]["text"]);
+    }
+
+    #[test]
+    fn timed_line_at() {
+        let line = |begin: u64, end: u64, text: &str| TimedLine {
+            begin: Timestamp::from_millis(begin),
+            end: Timestamp::from_millis(end),
+            text: text.to_string(),
+        };
+        let lines = vec![line(1000, 2000, "a"), line(2500, 3000, "b")];
+        assert_eq!(Some("a"), TimedLine::at(&lines, 1000));
+        assert_eq!(None, TimedLine::at(&lines, 2200));
+        assert_eq!(Some("b"), TimedLine::at(&lines, 2999));
+    }
+}
@@ -0,0 +1,134 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix of encrypted config values: `enc:<base64(salt | nonce | ciphertext)>`
+const PREFIX: &str = "enc:";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+const ENV_KEY_FILE: &str = "SIREN_KEY_FILE";
+const ENV_PASSPHRASE: &str = "SIREN_PASSPHRASE";
+
+/// Passphrase or key file contents used to seal secrets in the config file
+#[derive(Clone)]
+pub struct SecretKey {
+    material: Vec<u8>,
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(***)")
+    }
+}
+
+impl SecretKey {
+    pub(crate) fn from_passphrase(passphrase: &str) -> Self {
+        Self {
+            material: passphrase.as_bytes().to_vec(),
+        }
+    }
+
+    pub(crate) fn from_file(path: &Path) -> Result<Self, String> {
+        let material = fs::read(path)
+            .map_err(|error| format!("Unable to read key file {}: {}", path.display(), error))?;
+        let material = material.trim_ascii().to_vec();
+        if material.is_empty() {
+            Err(format!("Key file {} is empty", path.display()))?
+        }
+        Ok(Self { material })
+    }
+
+    /// Find key from `key_file`, then `SIREN_KEY_FILE`, then `SIREN_PASSPHRASE`
+    pub(crate) fn from_env(key_file: Option<&Path>) -> Result<Option<Self>, String> {
+        if let Some(path) = key_file {
+            return Self::from_file(path).map(Some);
+        }
+        if let Ok(path) = std::env::var(ENV_KEY_FILE) {
+            return Self::from_file(&PathBuf::from(path)).map(Some);
+        }
+        match std::env::var(ENV_PASSPHRASE) {
+            Ok(passphrase) if !passphrase.is_empty() => {
+                Ok(Some(Self::from_passphrase(&passphrase)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn is_encrypted(value: &str) -> bool {
+        value.starts_with(PREFIX)
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher(&salt)?
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| "Unable to encrypt secret".to_string())?;
+
+        let mut sealed = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(sealed)))
+    }
+
+    pub(crate) fn decrypt(&self, value: &str) -> Result<String, String> {
+        let encoded = match value.strip_prefix(PREFIX) {
+            Some(encoded) => encoded,
+            None => Err("value is not encrypted".to_string())?,
+        };
+        let sealed = STANDARD
+            .decode(encoded.trim())
+            .map_err(|error| format!("invalid encrypted value: {}", error))?;
+        if sealed.len() <= SALT_LEN + NONCE_LEN {
+            Err("invalid encrypted value: too short".to_string())?
+        }
+        let (salt, rest) = sealed.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let plaintext = self
+            .cipher(salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "unable to decrypt, wrong key?".to_string())?;
+        String::from_utf8(plaintext).map_err(|error| error.to_string())
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<Aes256Gcm, String> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&self.material, salt, &mut key)
+            .map_err(|error| error.to_string())?;
+        Aes256Gcm::new_from_slice(&key).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::services::secret_handler::SecretKey;
+
+    #[test]
+    fn encrypt_decrypt_test() {
+        let key = SecretKey::from_passphrase("passphrase");
+        let sealed = key.encrypt("secret").unwrap();
+        assert!(SecretKey::is_encrypted(&sealed));
+        assert!(!sealed.contains("secret"));
+        assert_eq!("secret", key.decrypt(&sealed).unwrap());
+    }
+
+    #[test]
+    fn decrypt_with_wrong_key() {
+        let sealed = SecretKey::from_passphrase("a").encrypt("secret").unwrap();
+        assert!(SecretKey::from_passphrase("b").decrypt(&sealed).is_err());
+        assert!(SecretKey::from_passphrase("a").decrypt("enc:AAAA").is_err());
+    }
+}
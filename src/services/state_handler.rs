@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Held for every load-modify-save, tasks update the state file at the same time
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
+/// Makes temp file names unique within the process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Data kept between restarts, saved as json next to the config file
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StateFile {
+    pub access_token: Option<AccessToken>,
+    /// Feishu system status owned by Siren, keyed by `app_id/status name`
+    pub feishu_status_id: HashMap<String, String>,
+    /// Open ids resolved from `user_list` entries, keyed by app id then entry
+    pub feishu_users: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AccessToken {
+    pub token: String,
+    /// Unix time in milliseconds
+    pub expire_time: u128,
+}
+
+impl std::fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"***")
+            .field("expire_time", &self.expire_time)
+            .finish()
+    }
+}
+
+impl AccessToken {
+    pub(crate) fn is_valid(&self, now: u128) -> bool {
+        now < self.expire_time
+    }
+}
+
+impl StateFile {
+    /// Load state file, a missing or broken file is treated as empty
+    pub(crate) fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match serde_json::from_str(&contents) {
+            Ok(state) => state,
+            Err(error) => {
+                eprintln!("Unable to parse state file {}: {}", path.display(), error);
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|error| error.to_string())?;
+        let temp_path = path.with_extension(format!(
+            "json.{}.{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .map_err(|error| error.to_string())?;
+        let written = file
+            .write_all(contents.as_bytes())
+            .and_then(|_| fs::rename(&temp_path, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written.map_err(|error| error.to_string())
+    }
+
+    /// Load, modify and save the state file, one update at a time
+    pub(crate) fn update<F>(path: &Path, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut StateFile),
+    {
+        let _lock = UPDATE_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut state = Self::load(path);
+        f(&mut state);
+        state.save(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::services::state_handler::StateFile;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn concurrent_updates() {
+        let path = std::env::temp_dir().join(format!("siren-state-{}.json", std::process::id()));
+        let threads: Vec<thread::JoinHandle<()>> = (0..8)
+            .map(|index| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    StateFile::update(&path, |state| {
+                        state
+                            .feishu_status_id
+                            .insert(index.to_string(), index.to_string());
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(8, StateFile::load(&path).feishu_status_id.len());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const ELLIPSIS: &str = "…";
+
+pub struct Text {}
+
+impl Text {
+    /// Display width in columns, wide characters like CJK and emoji count as 2
+    pub(crate) fn width(text: &str) -> usize {
+        text.graphemes(true).map(Self::grapheme_width).sum()
+    }
+
+    /// Cut `text` to `width` columns without splitting a grapheme, ending with an ellipsis,
+    /// empty when not even the ellipsis fits
+    pub(crate) fn truncate(text: &str, width: usize) -> String {
+        if Self::width(text) <= width {
+            return text.to_string();
+        }
+        if width < Self::width(ELLIPSIS) {
+            return String::new();
+        }
+        let width = width - Self::width(ELLIPSIS);
+        let mut truncated = String::new();
+        let mut used = 0;
+        for grapheme in text.graphemes(true) {
+            used += Self::grapheme_width(grapheme);
+            if used > width {
+                break;
+            }
+            truncated.push_str(grapheme);
+        }
+        truncated.push_str(ELLIPSIS);
+        truncated
+    }
+
+    /// Emoji sequences are drawn as one wide glyph, not the sum of their parts
+    fn grapheme_width(grapheme: &str) -> usize {
+        grapheme.width().min(2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::services::text_handler::Text;
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!("Song", Text::truncate("Song", 4));
+        assert_eq!("Lon…", Text::truncate("Long song", 4));
+        assert_eq!("無答…", Text::truncate("無答案", 5));
+        assert_eq!("無…", Text::truncate("無答案", 4));
+        assert_eq!("👨‍👩‍👧…", Text::truncate("👨‍👩‍👧👨‍👩‍👧", 3));
+        assert_eq!("e\u{301}…", Text::truncate("e\u{301}e\u{301}e\u{301}", 2));
+        assert_eq!("…", Text::truncate("Song", 1));
+        assert_eq!("", Text::truncate("Song", 0));
+        assert_eq!("", Text::truncate("", 0));
+    }
+}
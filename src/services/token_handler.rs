@@ -1,46 +1,122 @@
+use crate::config::Config;
+use crate::services::secret_handler::SecretKey;
+use crate::services::state_handler::{AccessToken, StateFile};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use fancy_regex::Regex;
-use serde_yaml::Value;
-use std::fs;
-use std::{
-    fs::File,
-    io::{stdin, Write},
-};
+use std::io::{stdin, IsTerminal};
+use std::path::Path;
+
+/// Lifetime assumed for access tokens without a readable `exp` claim
+const DEFAULT_ACCESS_TOKEN_TTL: u128 = 24 * 60 * 60 * 1000;
+/// Refresh access tokens this long before they expire, well within the default lifetime
+const ACCESS_TOKEN_REFRESH_MARGIN: u128 = 10 * 60 * 1000;
 
 pub struct Token {}
 
 impl Token {
-    pub(crate) fn get_user_token() -> String {
-        let config_path = "config.yml";
-
-        if !std::path::Path::new(config_path).exists() {
-            File::create(config_path).expect("Unable to create config file");
+    /// Get user token from config, or prompt for it and save it back to the config file
+    pub(crate) fn get_user_token(
+        config: &Config,
+        config_path: &Path,
+        key: Option<&SecretKey>,
+        interactive: bool,
+    ) -> Result<String, String> {
+        if let Some(token) = &config.user_token {
+            return Ok(token.to_string());
+        }
+        if !interactive || !stdin().is_terminal() {
+            Err(format!(
+                "user_token not found in {}, set user_token or user_token_file in config, or the SIREN_USER_TOKEN / SIREN_USER_TOKEN_FILE environment variable",
+                config_path.display()
+            ))?
+        }
+        println!("user_token not found in config, please enter user token:");
+        let token = Token::check_user_input()?;
+        let saved_token = match key {
+            Some(key) => key.encrypt(&token)?,
+            None => token.clone(),
+        };
+        match Config::write_fields(config_path, &[("user_token", saved_token)]) {
+            Ok(()) => println!("User token saved to {}", config_path.display()),
+            Err(error) => eprintln!("Unable to save user token: {}", error),
         }
+        Ok(token)
+    }
 
-        let config_content = fs::read_to_string(config_path).expect("Unable to read config file");
-        let config: Value =
-            serde_yaml::from_str(&config_content).expect("Unable to parse config file");
+    pub(crate) fn check_user_input() -> Result<String, String> {
+        let mut user_input = String::new();
+        println!("Enter user token: ");
+        stdin()
+            .read_line(&mut user_input)
+            .map_err(|error| error.to_string())?;
+        let user_input = user_input.trim().to_string();
+        if user_input.is_empty() {
+            Err("user token is empty".to_string())?
+        }
+        Ok(user_input)
+    }
 
-        match config["user_token"].as_str() {
-            Some(token) => token.to_string(),
-            None => {
-                println!("user_token not found in config, please enter user token:");
-                Token::check_user_input()
+    /// Get apple access token from the state file while it is valid, otherwise from web ui
+    pub(crate) async fn get_cached_access_token(state_path: &Path) -> Result<AccessToken, String> {
+        let cached = StateFile::load(state_path).access_token;
+        let now = chrono::Utc::now().timestamp_millis() as u128;
+        if let Some(access_token) = &cached {
+            if access_token.is_valid(now) {
+                return Ok(access_token.clone());
             }
         }
+        match Token::refresh_access_token(state_path).await {
+            Ok(access_token) => Ok(access_token),
+            Err(error) => match cached {
+                Some(access_token) => {
+                    eprintln!(
+                        "Unable to refresh access token: {}, using expired cached token",
+                        error
+                    );
+                    Ok(access_token)
+                }
+                None => Err(error),
+            },
+        }
     }
 
-    pub(crate) fn check_user_input() -> String {
-        let mut user_input = String::new();
-        println!("Enter user token: ");
-        stdin().read_line(&mut user_input).unwrap();
-        user_input = match user_input.strip_suffix('\n') {
-            Some(result) => result.to_string(),
-            None => user_input,
+    /// Get apple access token from web ui and save it to the state file
+    pub(crate) async fn refresh_access_token(state_path: &Path) -> Result<AccessToken, String> {
+        let token = Token::get_access_token().await?;
+        let now = chrono::Utc::now().timestamp_millis() as u128;
+        let access_token = AccessToken {
+            expire_time: Token::get_expire_time(&token)
+                .unwrap_or_else(|| Token::default_expire_time(now)),
+            token,
         };
-        if user_input.is_empty() {
-            panic!("user token is empty");
+        if let Err(error) = StateFile::update(state_path, |state| {
+            state.access_token = Some(access_token.clone())
+        }) {
+            eprintln!(
+                "Unable to save state file {}: {}",
+                state_path.display(),
+                error
+            );
         }
-        user_input
+        Ok(access_token)
+    }
+
+    /// When a token expiring at `expire_time` should be replaced
+    pub(crate) fn refresh_time(expire_time: u128) -> u128 {
+        expire_time.saturating_sub(ACCESS_TOKEN_REFRESH_MARGIN)
+    }
+
+    /// Expire time of a token issued at `now` without an `exp` claim
+    fn default_expire_time(now: u128) -> u128 {
+        now + DEFAULT_ACCESS_TOKEN_TTL
+    }
+
+    /// Read the `exp` claim of a jwt in milliseconds
+    fn get_expire_time(token: &str) -> Option<u128> {
+        let payload = token.trim_start_matches("Bearer ").split('.').nth(1)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let payload: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+        payload["exp"].as_u64().map(|exp| exp as u128 * 1000)
     }
 
     /// Get apple access token form web ui
@@ -53,15 +129,18 @@ impl Token {
         }
         let res_text = res.text().await.map_err(|error| error.to_string())?;
         let js_re = Regex::new(r#"(?<=index)(.*?)(?=\.js")"#).unwrap();
-        let js_file = js_re.find(&res_text).unwrap().unwrap().as_str();
+        let js_file = match js_re.find(&res_text) {
+            Ok(Some(value)) => value.as_str(),
+            _ => Err("Unable to find js file".to_string())?,
+        };
         println!("{:#?}", js_file);
         let js_res = reqwest::get(format!("https://music.apple.com/assets/index{js_file}.js"))
             .await
-            .unwrap();
+            .map_err(|error| error.to_string())?;
         if js_res.status().as_u16() != 200 {
             Err("Unable to get js file".to_string())?
         }
-        let js_res_text = js_res.text().await.unwrap();
+        let js_res_text = js_res.text().await.map_err(|error| error.to_string())?;
         // println!("{:#?}", js_res_text);
         let token_re = Regex::new(r#"(?=eyJh)(.*?)(?=")"#).unwrap();
         let token = match token_re.find(&js_res_text) {
@@ -74,3 +153,24 @@ impl Token {
         Ok(format!("Bearer {token}"))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::services::token_handler::Token;
+
+    #[test]
+    fn get_expire_time_test() {
+        // {"alg":"ES256"}.{"iss":"siren","exp":1700000000}.signature
+        let token = "Bearer eyJhbGciOiJFUzI1NiJ9.eyJpc3MiOiJzaXJlbiIsImV4cCI6MTcwMDAwMDAwMH0.c2ln";
+        assert_eq!(Some(1_700_000_000_000), Token::get_expire_time(token));
+        assert_eq!(None, Token::get_expire_time("Bearer invalid"));
+    }
+
+    #[test]
+    fn fresh_token_not_due() {
+        let now = 1_700_000_000_000;
+        let refresh_time = Token::refresh_time(Token::default_expire_time(now));
+        assert!(refresh_time > now + 60 * 60 * 1000);
+        assert_eq!(0, Token::refresh_time(0));
+    }
+}